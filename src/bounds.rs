@@ -0,0 +1,87 @@
+/// Confidence interval of an estimate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+  pub lower: u64,
+  pub estimate: u64,
+
+  /// Upper bound of the interval. `u64::MAX` if the estimate is not bounded from above.
+  pub upper: u64
+}
+
+impl Bounds {
+
+  /// Confidence interval of an asymptotically normal estimate with given relative standard error
+  pub fn normal(estimate: u64, standard_error: f64, confidence: f64) -> Bounds {
+    let deviation = estimate as f64 * standard_error * z_score(confidence);
+    Bounds {
+      lower: (estimate as f64 - deviation).max(0.0).floor() as u64,
+      estimate,
+      upper: (estimate as f64 + deviation).ceil() as u64
+    }
+  }
+}
+
+/// Number of standard deviations around the mean of the normal distribution covering given
+/// probability (eg. 1.96 for 0.95)
+pub fn z_score(confidence: f64) -> f64 {
+  assert!(confidence > 0.0 && confidence < 1.0, "Confidence should be between 0 and 1");
+  normal_quantile((1.0 + confidence) / 2.0)
+}
+
+/// Inverse of the standard normal CDF
+///
+/// Uses rational approximation by Peter J. Acklam with relative error less than 1.15e-9.
+fn normal_quantile(p: f64) -> f64 {
+  const A: [f64; 6] = [-3.969683028665376e+01, 2.209460984245205e+02, -2.759285104469687e+02,
+    1.38357751867269e+02, -3.066479806614716e+01, 2.506628277459239e+00];
+  const B: [f64; 5] = [-5.447609879822406e+01, 1.615858368580409e+02, -1.556989798598866e+02,
+    6.680131188771972e+01, -1.328068155288572e+01];
+  const C: [f64; 6] = [-7.784894002430293e-03, -3.223964580411365e-01, -2.400758277161838e+00,
+    -2.549732539343734e+00, 4.374664141464968e+00, 2.938163982698783e+00];
+  const D: [f64; 4] = [7.784695709041462e-03, 3.224671290700398e-01, 2.445134137142996e+00,
+    3.754408661907416e+00];
+  const P_LOW: f64 = 0.02425;
+
+  let tail = |q: f64| (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+    / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0);
+
+  if p < P_LOW {
+    tail((-2.0 * p.ln()).sqrt())
+  } else if p > 1.0 - P_LOW {
+    -tail((-2.0 * (1.0 - p).ln()).sqrt())
+  } else {
+    let q = p - 0.5;
+    let r = q * q;
+    (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+      / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn assert_close(expected: f64, actual: f64) {
+    assert!((expected - actual).abs() < 1e-6, "Expected {}, got {}", expected, actual);
+  }
+
+  #[test]
+  fn z_scores() {
+    assert_close(1.959964, z_score(0.95));
+    assert_close(2.575829, z_score(0.99));
+    assert_close(0.674490, z_score(0.5));
+    assert_close(3.290527, z_score(0.999));
+  }
+
+  #[test]
+  fn normal_bounds() {
+    let bounds = Bounds::normal(1000, 0.01, 0.95);
+    assert_eq!(Bounds { lower: 980, estimate: 1000, upper: 1020 }, bounds);
+  }
+
+  #[test]
+  fn lower_bound_is_not_negative() {
+    assert_eq!(0, Bounds::normal(10, 1.0, 0.99).lower);
+  }
+}
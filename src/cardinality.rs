@@ -0,0 +1,217 @@
+use error::MergeError;
+use bounds::Bounds;
+use std::io::{self, Write};
+use linear_counter::{LinearCounter, Estimate};
+use hyper_log_log::HyperLogLog;
+use hash::SketchHasher;
+use sketch_file::{self, SketchFile, SketchKind, Header};
+
+/// Cardinality estimator used by `uniq` subcommand
+pub enum Cardinality {
+  Linear(LinearCounter),
+  HyperLogLog(HyperLogLog),
+
+  /// Feeds both estimators and reports the one with the lowest expected error. Linear counting is
+  /// more precise while the buffer is sparse, but it degrades quickly when the number of unique
+  /// values is much larger than the buffer size.
+  Auto(LinearCounter, HyperLogLog)
+}
+
+/// Estimator selected by `Cardinality`
+pub enum Estimator<'a> {
+  Linear(&'a LinearCounter),
+  HyperLogLog(&'a HyperLogLog)
+}
+
+impl Cardinality {
+
+  pub fn offer(&mut self, line: &str) {
+    match *self {
+      Cardinality::Linear(ref mut lc) => lc.offer_bytes(line.as_bytes()),
+      Cardinality::HyperLogLog(ref mut hll) => hll.offer_bytes(line.as_bytes()),
+      Cardinality::Auto(ref mut lc, ref mut hll) => {
+        let hash = lc.hasher().hash_bytes(line.as_bytes());
+        lc.offer_hash(hash);
+        hll.offer_hash(hash);
+      }
+    }
+  }
+
+  /// Returns an estimator used for estimation. In `Auto` mode it is the estimator with the lowest
+  /// expected error.
+  pub fn selected(&self) -> Estimator<'_> {
+    match *self {
+      Cardinality::Linear(ref lc) => Estimator::Linear(lc),
+      Cardinality::HyperLogLog(ref hll) => Estimator::HyperLogLog(hll),
+      Cardinality::Auto(ref lc, ref hll) => {
+        if lc.standard_error() <= hll.standard_error() {
+          Estimator::Linear(lc)
+        } else {
+          Estimator::HyperLogLog(hll)
+        }
+      }
+    }
+  }
+
+  /// Estimate of the selected estimator. HyperLogLog estimates are always accurate.
+  pub fn estimate(&self) -> Estimate {
+    match self.selected() {
+      Estimator::Linear(lc) => lc.estimate(),
+      Estimator::HyperLogLog(hll) => Estimate::Accurate(hll.estimate())
+    }
+  }
+
+  /// Estimate of the selected estimator with a confidence interval
+  pub fn estimate_with_bounds(&self, confidence: f64) -> Bounds {
+    match self.selected() {
+      Estimator::Linear(lc) => lc.estimate_with_bounds(confidence),
+      Estimator::HyperLogLog(hll) => hll.estimate_with_bounds(confidence)
+    }
+  }
+
+  /// Relative standard error of the estimate
+  pub fn standard_error(&self) -> f64 {
+    match self.selected() {
+      Estimator::Linear(lc) => lc.standard_error(),
+      Estimator::HyperLogLog(hll) => hll.standard_error()
+    }
+  }
+
+  /// Number of bytes used by the estimator (both estimators in `Auto` mode)
+  pub fn memory_usage(&self) -> usize {
+    match *self {
+      Cardinality::Linear(ref lc) => lc.memory_usage(),
+      Cardinality::HyperLogLog(ref hll) => hll.memory_usage(),
+      Cardinality::Auto(ref lc, ref hll) => lc.memory_usage() + hll.memory_usage()
+    }
+  }
+
+  /// Merges other estimator into this one. Both estimators should use the same algorithm.
+  pub fn merge(&mut self, other: &Cardinality) -> Result<(), MergeError> {
+    match (self, other) {
+      (Cardinality::Linear(a), Cardinality::Linear(b)) => a.merge(b),
+      (Cardinality::HyperLogLog(a), Cardinality::HyperLogLog(b)) => a.merge(b),
+      (Cardinality::Auto(a_lc, a_hll), Cardinality::Auto(b_lc, b_hll)) => {
+        a_lc.merge(b_lc)?;
+        a_hll.merge(b_hll)
+      },
+      _ => Err(MergeError::TypeMismatch)
+    }
+  }
+
+  /// Writes the state of the estimator. In `Auto` mode both estimators are written, so the type of
+  /// the file doesn't depend on the number of unique values and sketches of different streams
+  /// could be merged.
+  pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    match *self {
+      Cardinality::Linear(ref lc) => lc.write_to(w),
+      Cardinality::HyperLogLog(ref hll) => hll.write_to(w),
+      Cardinality::Auto(ref lc, ref hll) => {
+        let mut payload = vec![];
+        lc.write_to(&mut payload)?;
+        hll.write_to(&mut payload)?;
+        let header = Header {
+          kind: SketchKind::LinearAndHyperLogLog,
+          size: lc.size() as u64,
+          hash: lc.hasher().id(),
+          seed: lc.hasher().seed()
+        };
+        SketchFile::new(header, payload).write_to(w)
+      }
+    }
+  }
+
+  /// Restores an estimator from the sketch file. Estimation algorithm is defined by the file.
+  pub fn from_sketch(file: SketchFile) -> io::Result<Cardinality> {
+    match file.header.kind {
+      SketchKind::LinearCounter => LinearCounter::from_sketch(file).map(Cardinality::Linear),
+      SketchKind::HyperLogLog => HyperLogLog::from_sketch(file).map(Cardinality::HyperLogLog),
+      SketchKind::LinearAndHyperLogLog => {
+        let mut payload = &file.payload[..];
+        let lc: LinearCounter = LinearCounter::read_from(&mut payload)?;
+        let hll: HyperLogLog = HyperLogLog::read_from(&mut payload)?;
+        if !payload.is_empty() {
+          return Err(sketch_file::invalid_data("Unexpected data after HyperLogLog"));
+        }
+        // Both estimators are fed with the same hash of an item
+        if !lc.hasher().same_as(hll.hasher()) {
+          return Err(sketch_file::invalid_data("Estimators use different hash functions"));
+        }
+        Ok(Cardinality::Auto(lc, hll))
+      },
+      kind => Err(sketch_file::invalid_data(&format!("{:?} is not a cardinality estimator", kind)))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn auto_uses_linear_counter_for_sparse_buffer() {
+    let mut counter = Cardinality::Auto(LinearCounter::new(1000), HyperLogLog::new(4));
+    for j in 0..100 {
+      counter.offer(&format!("str{}", j));
+    }
+    assert_eq!(Estimate::Accurate(100), counter.estimate());
+  }
+
+  #[test]
+  fn auto_uses_hyper_log_log_for_saturated_buffer() {
+    let mut counter = Cardinality::Auto(LinearCounter::new(1), HyperLogLog::new(14));
+    for j in 0..1000 {
+      counter.offer(&format!("str{}", j));
+    }
+    let error = (counter.estimate().value() as f64 - 1000.0).abs() / 1000.0;
+    assert!(error < 0.05, "Error is too high: {}", error);
+  }
+
+  #[test]
+  fn merge_different_algorithms() {
+    let mut a = Cardinality::Linear(LinearCounter::new(10));
+    let b = Cardinality::HyperLogLog(HyperLogLog::new(10));
+    assert_eq!(Err(MergeError::TypeMismatch), a.merge(&b));
+  }
+
+  fn auto(size: usize, precision: u8, items: usize) -> Cardinality {
+    let mut counter = Cardinality::Auto(LinearCounter::new(size), HyperLogLog::new(precision));
+    for j in 0..items {
+      counter.offer(&format!("str{}", j));
+    }
+    counter
+  }
+
+  fn write_and_read(counter: &Cardinality) -> Cardinality {
+    let mut bytes = vec![];
+    counter.write_to(&mut bytes).unwrap();
+    Cardinality::from_sketch(SketchFile::read_from(&mut &bytes[..]).unwrap()).unwrap()
+  }
+
+  #[test]
+  fn auto_write_and_read() {
+    let counter = auto(100, 10, 1000);
+    let restored = write_and_read(&counter);
+    assert_eq!(counter.estimate(), restored.estimate());
+    match restored {
+      Cardinality::Auto(lc, hll) => {
+        assert_eq!(100, lc.size());
+        assert_eq!(10, hll.precision());
+      },
+      _ => panic!("Both estimators should be restored")
+    }
+  }
+
+  #[test]
+  fn merge_sparse_and_dense_auto() {
+    let mut sparse = write_and_read(&auto(100, 12, 10));
+    let dense = write_and_read(&auto(100, 12, 10000));
+    assert!(matches!(sparse.selected(), Estimator::Linear(_)));
+    assert!(matches!(dense.selected(), Estimator::HyperLogLog(_)));
+
+    sparse.merge(&dense).unwrap();
+    let error = (sparse.estimate().value() as f64 - 10000.0).abs() / 10000.0;
+    assert!(error < 0.05, "Error is too high: {}", error);
+  }
+}
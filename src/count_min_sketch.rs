@@ -0,0 +1,368 @@
+use error::MergeError;
+use bounds::Bounds;
+use hash::{SketchHasher, HashFunction};
+use std::f64::consts::E;
+use std::hash::Hash;
+use sketch_file::{self, SketchFile, SketchKind, Header};
+use std::io::{self, Read, Write};
+use std::mem::size_of;
+
+/// Count-Min sketch
+///
+/// Sketch is a matrix of `depth` rows of `width` counters. Each row is associated with its own
+/// hash function and each incoming item increments one counter in every row. Count of an item is
+/// estimated as the minimum of its counters, so it's never underestimated. With probability
+/// `1 - δ` the estimate exceeds the real count by at most `εN`, where `N` is the total count,
+/// `ε = e / width` and `δ = e^-depth`.
+///
+/// Unlike `StreamSummary` sketch doesn't store the elements, so it could answer queries for any
+/// element, but could not list the most frequent ones.
+///
+/// Conservative update is used: only the counters which are less than the new estimate of the item
+/// are increased. It never makes the error larger, while reducing it considerably for skewed
+/// streams.
+///
+/// Detailed explanation of the algorithm: [An Improved Data Stream Summary: The Count-Min Sketch and
+/// its Applications](http://dimacs.rutgers.edu/~graham/pubs/papers/cm-full.pdf)
+pub struct CountMinSketch<H = HashFunction> {
+  width: usize,
+  depth: usize,
+
+  /// Rows of counters one after another
+  counters: Vec<u64>,
+  total: u64,
+  hasher: H
+}
+
+impl CountMinSketch<HashFunction> {
+
+  pub fn new(width: usize, depth: usize) -> CountMinSketch<HashFunction> {
+    Self::with_hasher(width, depth, HashFunction::default())
+  }
+
+  /// Creates a sketch overestimating counts by at most `epsilon` fraction of the total count with
+  /// probability `1 - delta` (see `width_for_error` and `depth_for_error`)
+  pub fn with_error(epsilon: f64, delta: f64) -> CountMinSketch<HashFunction> {
+    Self::with_hasher(width_for_error(epsilon), depth_for_error(delta), HashFunction::default())
+  }
+}
+
+/// Calculates the smallest width of a sketch overestimating counts by at most `epsilon` fraction
+/// of the total count: `width = ⌈e / ε⌉`
+pub fn width_for_error(epsilon: f64) -> usize {
+  assert!(epsilon > 0.0 && epsilon < 1.0, "Epsilon should be between 0 and 1");
+  (E / epsilon).ceil() as usize
+}
+
+/// Calculates the smallest depth of a sketch exceeding the error bound with at most `delta`
+/// probability: `depth = ⌈ln(1 / δ)⌉`
+pub fn depth_for_error(delta: f64) -> usize {
+  assert!(delta > 0.0 && delta < 1.0, "Delta should be between 0 and 1");
+  (1.0 / delta).ln().ceil().max(1.0) as usize
+}
+
+/// Calculates the largest width of a sketch with given depth fitting into given number of bytes
+pub fn width_for_memory(bytes: usize, depth: usize) -> usize {
+  bytes.saturating_sub(size_of::<CountMinSketch>()) / size_of::<u64>() / depth.max(1)
+}
+
+impl<H: SketchHasher> CountMinSketch<H> {
+
+  pub fn with_hasher(width: usize, depth: usize, hasher: H) -> CountMinSketch<H> {
+    assert!(width > 0 && depth > 0, "Sketch should have at least one counter");
+    CountMinSketch { width, depth, counters: vec![0; width * depth], total: 0, hasher }
+  }
+
+  pub fn hasher(&self) -> &H {
+    &self.hasher
+  }
+
+  /// Number of counters in each row
+  pub fn width(&self) -> usize {
+    self.width
+  }
+
+  /// Number of rows
+  pub fn depth(&self) -> usize {
+    self.depth
+  }
+
+  /// Total count of all the items offered to the sketch
+  pub fn total(&self) -> u64 {
+    self.total
+  }
+
+  /// Maximum overestimation of counts as a fraction of the total count
+  pub fn epsilon(&self) -> f64 {
+    E / self.width as f64
+  }
+
+  /// Probability of overestimating a count by more than `epsilon` fraction of the total count
+  pub fn delta(&self) -> f64 {
+    (-(self.depth as f64)).exp()
+  }
+
+  /// Updates a sketch with given item
+  pub fn offer<T: Hash + ?Sized>(&mut self, item: &T) -> u64 {
+    let hash = self.hasher.hash(item);
+    self.offer_hash(hash, 1)
+  }
+
+  /// Updates a sketch with raw bytes of an item
+  pub fn offer_bytes(&mut self, bytes: &[u8]) -> u64 {
+    self.offer_weighted_bytes(bytes, 1)
+  }
+
+  /// Updates a sketch with raw bytes of an item occurring `weight` times
+  pub fn offer_weighted_bytes(&mut self, bytes: &[u8], weight: u64) -> u64 {
+    let hash = self.hasher.hash_bytes(bytes);
+    self.offer_hash(hash, weight)
+  }
+
+  /// Updates a sketch with already computed hash of an item occurring `weight` times. Hash should
+  /// be computed using the hasher of this sketch. Returns new estimate of the item count.
+  pub fn offer_hash(&mut self, hash: u64, weight: u64) -> u64 {
+    self.total = self.total.saturating_add(weight);
+    let estimate = self.estimate_hash(hash).saturating_add(weight);
+    for row in 0..self.depth {
+      let idx = self.counter_idx(hash, row);
+      let counter = &mut self.counters[idx];
+      *counter = (*counter).max(estimate);
+    }
+    estimate
+  }
+
+  /// Estimates count of given item
+  pub fn estimate<T: Hash + ?Sized>(&self, item: &T) -> u64 {
+    self.estimate_hash(self.hasher.hash(item))
+  }
+
+  /// Estimates count of an item given by its raw bytes
+  pub fn estimate_bytes(&self, bytes: &[u8]) -> u64 {
+    self.estimate_hash(self.hasher.hash_bytes(bytes))
+  }
+
+  /// Estimates count of an item with already computed hash
+  pub fn estimate_hash(&self, hash: u64) -> u64 {
+    (0..self.depth)
+      .map(|row| self.counters[self.counter_idx(hash, row)])
+      .min()
+      .unwrap_or(0)
+  }
+
+  /// Estimates count of an item given by its raw bytes with bounds. Estimate is the upper bound
+  /// of the count, and the lower bound holds with probability `1 - delta`.
+  pub fn estimate_bytes_with_bounds(&self, bytes: &[u8]) -> Bounds {
+    let estimate = self.estimate_bytes(bytes);
+    let error = (self.epsilon() * self.total as f64).ceil() as u64;
+    Bounds { lower: estimate.saturating_sub(error), estimate, upper: estimate }
+  }
+
+  /// Merges other sketch into this one
+  ///
+  /// After merging the sketch estimates counts in the union of both streams. Sketches should have
+  /// the same dimensions and hash function.
+  pub fn merge(&mut self, other: &CountMinSketch<H>) -> Result<(), MergeError> {
+    if self.width != other.width {
+      return Err(MergeError::SizeMismatch { expected: self.width, actual: other.width });
+    }
+    if self.depth != other.depth {
+      return Err(MergeError::SizeMismatch { expected: self.depth, actual: other.depth });
+    }
+    if !self.hasher.same_as(&other.hasher) {
+      return Err(MergeError::HashMismatch);
+    }
+    for (counter, other_counter) in self.counters.iter_mut().zip(&other.counters) {
+      *counter = counter.saturating_add(*other_counter);
+    }
+    self.total = self.total.saturating_add(other.total);
+    Ok(())
+  }
+
+  /// Number of bytes used by the sketch
+  pub fn memory_usage(&self) -> usize {
+    size_of::<Self>() + self.counters.capacity() * size_of::<u64>()
+  }
+
+  /// Writes the state of the sketch in the sketch file format
+  ///
+  /// Size in the header is the width of the sketch. Payload contains depth and total count followed
+  /// by the counters row by row.
+  pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    let mut payload = Vec::with_capacity((self.counters.len() + 2) * 8);
+    sketch_file::write_u64(&mut payload, self.depth as u64)?;
+    sketch_file::write_u64(&mut payload, self.total)?;
+    for counter in &self.counters {
+      sketch_file::write_u64(&mut payload, *counter)?;
+    }
+    let header = Header {
+      kind: SketchKind::CountMin,
+      size: self.width as u64,
+      hash: self.hasher.id(),
+      seed: self.hasher.seed()
+    };
+    SketchFile::new(header, payload).write_to(w)
+  }
+
+  /// Reads sketch previously written with `write_to`
+  pub fn read_from<R: Read>(r: &mut R) -> io::Result<CountMinSketch<H>> {
+    Self::from_sketch(SketchFile::read_from(r)?)
+  }
+
+  pub fn from_sketch(file: SketchFile) -> io::Result<CountMinSketch<H>> {
+    file.expect_kind(SketchKind::CountMin)?;
+    let hasher = H::from_id(file.header.hash, file.header.seed)
+      .ok_or_else(|| sketch_file::invalid_data("Unsupported hash function"))?;
+
+    let mut payload = &file.payload[..];
+    let width = file.header.size as usize;
+    let depth = sketch_file::read_u64(&mut payload)? as usize;
+    let total = sketch_file::read_u64(&mut payload)?;
+    let size = width.checked_mul(depth).filter(|size| *size > 0);
+    if size.and_then(|size| size.checked_mul(8)) != Some(payload.len()) {
+      return Err(sketch_file::invalid_data("Invalid count-min sketch size"));
+    }
+
+    let mut counters = Vec::with_capacity(width * depth);
+    for _ in 0..width * depth {
+      counters.push(sketch_file::read_u64(&mut payload)?);
+    }
+    Ok(CountMinSketch { width, depth, counters, total, hasher })
+  }
+
+  /// Index of the counter of given row linked to given hash sum
+  ///
+  /// Hash functions of rows are derived from two halves of the hash sum as `h1 + i * h2` (see
+  /// [Less Hashing, Same Performance](https://www.eecs.harvard.edu/~michaelm/postscripts/rsa2008.pdf)).
+  fn counter_idx(&self, hash: u64, row: usize) -> usize {
+    let h1 = hash & 0xFFFF_FFFF;
+    let h2 = hash >> 32;
+    let column = h1.wrapping_add((row as u64).wrapping_mul(h2)) % self.width as u64;
+    row * self.width + column as usize
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn stream(sketch: &mut CountMinSketch) {
+    for i in 0..1000u64 {
+      for _ in 0..i % 10 + 1 {
+        sketch.offer_bytes(i.to_string().as_bytes());
+      }
+    }
+  }
+
+  #[test]
+  fn counts_are_never_underestimated() {
+    let mut sketch = CountMinSketch::new(200, 4);
+    stream(&mut sketch);
+
+    assert_eq!(5500, sketch.total());
+    let error = (sketch.epsilon() * sketch.total() as f64) as u64;
+    for i in 0..1000u64 {
+      let estimate = sketch.estimate_bytes(i.to_string().as_bytes());
+      assert!(estimate > i % 10, "{} is underestimated: {}", i, estimate);
+      assert!(estimate <= i % 10 + 1 + error, "{} is overestimated: {}", i, estimate);
+    }
+  }
+
+  #[test]
+  fn exact_counts_without_collisions() {
+    let mut sketch = CountMinSketch::new(1000, 3);
+    assert_eq!(1, sketch.offer_bytes(b"foo"));
+    assert_eq!(2, sketch.offer_bytes(b"foo"));
+    assert_eq!(10, sketch.offer_weighted_bytes(b"bar", 10));
+    assert_eq!(2, sketch.estimate_bytes(b"foo"));
+    assert_eq!(10, sketch.estimate_bytes(b"bar"));
+    assert_eq!(0, sketch.estimate_bytes(b"baz"));
+    assert_eq!(1, sketch.offer(&42u64));
+    assert_eq!(1, sketch.estimate(&42u64));
+  }
+
+  #[test]
+  fn conservative_update() {
+    // Single column, so all the items collide
+    let mut sketch = CountMinSketch::new(1, 2);
+    sketch.offer_weighted_bytes(b"foo", 5);
+    sketch.offer_weighted_bytes(b"bar", 3);
+
+    // Counter is raised only to the new estimate of "bar", which is 5 + 3
+    assert_eq!(8, sketch.estimate_bytes(b"foo"));
+    assert_eq!(vec![8, 8], sketch.counters);
+  }
+
+  #[test]
+  fn bounds() {
+    let mut sketch = CountMinSketch::new(27, 2);
+    stream(&mut sketch);
+
+    let bounds = sketch.estimate_bytes_with_bounds(b"42");
+    let error = (sketch.epsilon() * 5500.0).ceil() as u64;
+    assert_eq!(bounds.estimate, bounds.upper);
+    assert_eq!(bounds.estimate.saturating_sub(error), bounds.lower);
+    assert!(bounds.lower <= 3 && bounds.upper >= 3);
+  }
+
+  #[test]
+  fn dimensions() {
+    assert_eq!(272, width_for_error(0.01));
+    assert_eq!(28, width_for_error(0.1));
+    assert_eq!(5, depth_for_error(0.01));
+    assert_eq!(1, depth_for_error(0.5));
+
+    let sketch = CountMinSketch::with_error(0.001, 0.001);
+    assert!(sketch.epsilon() <= 0.001);
+    assert!(sketch.delta() <= 0.001);
+  }
+
+  #[test]
+  fn memory() {
+    let budget = 1 << 20;
+    let width = width_for_memory(budget, 4);
+    let sketch = CountMinSketch::new(width, 4);
+    assert!(sketch.memory_usage() <= budget);
+    assert!(sketch.memory_usage() > budget - 4 * 8);
+  }
+
+  #[test]
+  fn merge() {
+    let mut a = CountMinSketch::new(100, 3);
+    let mut b = CountMinSketch::new(100, 3);
+    a.offer_weighted_bytes(b"foo", 3);
+    b.offer_weighted_bytes(b"foo", 4);
+    b.offer_bytes(b"bar");
+
+    a.merge(&b).unwrap();
+    assert_eq!(7, a.estimate_bytes(b"foo"));
+    assert_eq!(8, a.total());
+  }
+
+  #[test]
+  fn merge_mismatch() {
+    let mut a = CountMinSketch::new(100, 3);
+    assert_eq!(Err(MergeError::SizeMismatch { expected: 100, actual: 50 }),
+      a.merge(&CountMinSketch::new(50, 3)));
+    assert_eq!(Err(MergeError::SizeMismatch { expected: 3, actual: 4 }),
+      a.merge(&CountMinSketch::new(100, 4)));
+    let other = CountMinSketch::with_hasher(100, 3, HashFunction::Md5);
+    assert_eq!(Err(MergeError::HashMismatch), a.merge(&other));
+  }
+
+  #[test]
+  fn write_and_read() {
+    let mut sketch = CountMinSketch::with_hasher(100, 3, HashFunction::SipHash { seed: 7 });
+    stream(&mut sketch);
+
+    let mut bytes = vec![];
+    sketch.write_to(&mut bytes).unwrap();
+    let restored: CountMinSketch = CountMinSketch::read_from(&mut &bytes[..]).unwrap();
+
+    assert_eq!((100, 3), (restored.width(), restored.depth()));
+    assert_eq!(sketch.total(), restored.total());
+    assert_eq!(sketch.counters, restored.counters);
+    assert_eq!(HashFunction::SipHash { seed: 7 }, *restored.hasher());
+  }
+}
@@ -0,0 +1,32 @@
+use std::error::Error;
+use std::fmt;
+
+/// Error returned when two sketches could not be merged
+#[derive(Debug, PartialEq)]
+pub enum MergeError {
+
+  /// Sketches have different sizes (buffer words, registers etc.)
+  SizeMismatch { expected: usize, actual: usize },
+
+  /// Sketches are of different types (eg. linear counter and HyperLogLog)
+  TypeMismatch,
+
+  /// Sketches are built using different hash functions
+  HashMismatch
+}
+
+impl fmt::Display for MergeError {
+
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      MergeError::SizeMismatch { expected, actual } =>
+        write!(f, "Sketch size mismatch: expected {}, got {}", expected, actual),
+      MergeError::TypeMismatch =>
+        write!(f, "Sketches of different types could not be merged"),
+      MergeError::HashMismatch =>
+        write!(f, "Sketches built using different hash functions could not be merged")
+    }
+  }
+}
+
+impl Error for MergeError {}
@@ -0,0 +1,336 @@
+use serde_json::{self, Value};
+use regex::{Regex, CaptureLocations};
+
+/// Extracts keys from input lines before they are given to a sketch
+///
+/// Extractor owns a buffer which is reused for keys which are not a continuous part of a line
+/// (eg. composite keys), so no allocation is happening on each line.
+///
+/// # Examples
+/// ```
+/// let mut extractor = KeyExtractor::fields("\t", vec![1, 3]);
+/// assert_eq!(Some("a\tc"), extractor.extract("a\tb\tc"));
+/// ```
+pub struct KeyExtractor {
+  source: KeySource,
+  buffer: String,
+  missing: usize,
+  malformed: usize,
+  unmatched: usize
+}
+
+enum KeySource {
+
+  /// Whole line is a key
+  Line,
+
+  /// Key consists of one or more fields (zero based) separated by a delimiter
+  Fields { delimiter: String, fields: Vec<usize> },
+
+  /// Line is a JSON object and key is a value at given JSON pointer (eg. `/user/id`)
+  Json { pointer: String },
+
+  /// Key is a capture group of a regular expression. Lines not matching the expression are
+  /// skipped or counted under `unmatched` key if one is given.
+  Regex { regex: Regex, group: usize, locations: CaptureLocations, unmatched: Option<String> }
+}
+
+impl KeyExtractor {
+
+  /// Extractor using whole line as a key
+  pub fn line() -> Self {
+    Self::new(KeySource::Line)
+  }
+
+  /// Extractor using given fields (one based, as in `cut`) as a key. Composite keys are joined
+  /// using the same delimiter.
+  pub fn fields(delimiter: &str, fields: Vec<usize>) -> Self {
+    assert!(!fields.is_empty(), "At least one field should be given");
+    assert!(fields.iter().all(|f| *f > 0), "Fields are numbered from 1");
+    let fields = fields.iter().map(|f| f - 1).collect();
+    Self::new(KeySource::Fields { delimiter: delimiter.to_string(), fields })
+  }
+
+  /// Extractor parsing each line as a JSON and using a value at given [JSON pointer][rfc6901]
+  /// as a key. String values are used as is, all other values are used in JSON representation.
+  ///
+  /// [rfc6901]: https://tools.ietf.org/html/rfc6901
+  pub fn json(pointer: &str) -> Self {
+    Self::new(KeySource::Json { pointer: pointer.to_string() })
+  }
+
+  /// Extractor using a capture group of a regular expression as a key. Group could be given by
+  /// an index or by a name. If no group is given, first capture group is used or the whole match
+  /// if expression has no groups.
+  pub fn regex(pattern: &str, group: Option<&str>, unmatched: Option<&str>) -> Result<Self, String> {
+    let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+    let group = match group {
+      None if regex.captures_len() > 1 => 1,
+      None => 0,
+      Some(group) => match group.parse::<usize>() {
+        Ok(index) if index < regex.captures_len() => index,
+        Ok(index) => return Err(format!("Regex has no group {}", index)),
+        Err(_) => regex.capture_names()
+          .position(|name| name == Some(group))
+          .ok_or_else(|| format!("Regex has no group named '{}'", group))?
+      }
+    };
+    let locations = regex.capture_locations();
+    let unmatched = unmatched.map(str::to_string);
+    Ok(Self::new(KeySource::Regex { regex, group, locations, unmatched }))
+  }
+
+  fn new(source: KeySource) -> Self {
+    KeyExtractor { source, buffer: String::new(), missing: 0, malformed: 0, unmatched: 0 }
+  }
+
+  /// Returns a key for the given line or `None` if line should be skipped
+  pub fn extract<'a>(&'a mut self, line: &'a str) -> Option<&'a str> {
+    let key = match self.source {
+      KeySource::Line => Some(line),
+      KeySource::Fields { ref delimiter, ref fields } if fields.len() == 1 =>
+        line.split(delimiter.as_str()).nth(fields[0]),
+      KeySource::Fields { ref delimiter, ref fields } => {
+        self.buffer.clear();
+        let mut complete = true;
+        for (i, field) in fields.iter().enumerate() {
+          match line.split(delimiter.as_str()).nth(*field) {
+            Some(value) => {
+              if i > 0 {
+                self.buffer.push_str(delimiter);
+              }
+              self.buffer.push_str(value);
+            },
+            None => {
+              complete = false;
+              break;
+            }
+          }
+        }
+        if complete { Some(self.buffer.as_str()) } else { None }
+      },
+      KeySource::Json { ref pointer } => {
+        let json = match serde_json::from_str::<Value>(line) {
+          Ok(json) => json,
+          Err(_) => {
+            self.malformed += 1;
+            return None;
+          }
+        };
+        self.buffer.clear();
+        match json.pointer(pointer) {
+          Some(Value::String(value)) => self.buffer.push_str(value),
+          Some(value) => self.buffer.push_str(&value.to_string()),
+          None => {
+            self.missing += 1;
+            return None;
+          }
+        }
+        Some(self.buffer.as_str())
+      },
+      KeySource::Regex { ref regex, group, ref mut locations, ref unmatched } => {
+        let key = regex.captures_read(locations, line)
+          .and_then(|_| locations.get(group))
+          .map(|(start, end)| &line[start..end]);
+        if key.is_none() {
+          self.unmatched += 1;
+          return unmatched.as_ref().map(String::as_str);
+        }
+        key
+      }
+    };
+    if key.is_none() {
+      self.missing += 1;
+    }
+    key
+  }
+
+  /// Number of lines skipped because they don't have requested fields
+  pub fn missing(&self) -> usize {
+    self.missing
+  }
+
+  /// Number of lines not matching the regular expression. Those lines are either skipped or
+  /// counted under the unmatched key.
+  pub fn unmatched(&self) -> usize {
+    self.unmatched
+  }
+
+  /// Number of lines skipped because they could not be parsed
+  pub fn malformed(&self) -> usize {
+    self.malformed
+  }
+}
+
+/// Extracts weights of elements (eg. bytes transferred) from a field of input lines
+///
+/// # Examples
+/// ```
+/// let mut weights = WeightExtractor::field("\t", 2);
+/// assert_eq!(Some(512), weights.extract("/index.html\t512"));
+/// ```
+pub struct WeightExtractor {
+  delimiter: String,
+  field: usize,
+  invalid: usize
+}
+
+impl WeightExtractor {
+
+  /// Extractor using given field (one based) as a weight
+  pub fn field(delimiter: &str, field: usize) -> Self {
+    assert!(field > 0, "Fields are numbered from 1");
+    WeightExtractor { delimiter: delimiter.to_string(), field: field - 1, invalid: 0 }
+  }
+
+  /// Returns a weight for the given line or `None` if the field is missing or is not a non-negative
+  /// integer
+  pub fn extract(&mut self, line: &str) -> Option<u64> {
+    let weight = line.split(self.delimiter.as_str()).nth(self.field)
+      .and_then(|weight| weight.trim().parse().ok());
+    if weight.is_none() {
+      self.invalid += 1;
+    }
+    weight
+  }
+
+  /// Number of lines skipped because of missing or invalid weight
+  pub fn invalid(&self) -> usize {
+    self.invalid
+  }
+}
+
+/// Parses comma separated list of fields (eg. `1,3`)
+pub fn parse_fields(fields: &str) -> Result<Vec<usize>, String> {
+  fields.split(',')
+    .map(|f| match f.trim().parse::<usize>() {
+      Ok(n) if n > 0 => Ok(n),
+      _ => Err(format!("Invalid field number: '{}'", f))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn whole_line() {
+    let mut e = KeyExtractor::line();
+    assert_eq!(Some("foo bar"), e.extract("foo bar"));
+  }
+
+  #[test]
+  fn single_field() {
+    let mut e = KeyExtractor::fields(" ", vec![2]);
+    assert_eq!(Some("bar"), e.extract("foo bar baz"));
+  }
+
+  #[test]
+  fn composite_key() {
+    let mut e = KeyExtractor::fields(",", vec![3, 1]);
+    assert_eq!(Some("baz,foo"), e.extract("foo,bar,baz"));
+    assert_eq!(Some("3,1"), e.extract("1,2,3"));
+  }
+
+  #[test]
+  fn multi_char_delimiter() {
+    let mut e = KeyExtractor::fields(" | ", vec![2]);
+    assert_eq!(Some("bar"), e.extract("foo | bar"));
+  }
+
+  #[test]
+  fn missing_field() {
+    let mut e = KeyExtractor::fields(",", vec![1, 3]);
+    assert_eq!(None, e.extract("foo,bar"));
+    assert_eq!(None, e.extract(""));
+    assert_eq!(2, e.missing());
+  }
+
+  #[test]
+  fn json_string_value() {
+    let mut e = KeyExtractor::json("/user/name");
+    assert_eq!(Some("John"), e.extract(r#"{"user": {"name": "John"}}"#));
+  }
+
+  #[test]
+  fn json_non_string_value() {
+    let mut e = KeyExtractor::json("/user/id");
+    assert_eq!(Some("42"), e.extract(r#"{"user": {"id": 42}}"#));
+    assert_eq!(Some("[1,2]"), e.extract(r#"{"user": {"id": [1, 2]}}"#));
+  }
+
+  #[test]
+  fn json_array_index() {
+    let mut e = KeyExtractor::json("/tags/1");
+    assert_eq!(Some("b"), e.extract(r#"{"tags": ["a", "b"]}"#));
+  }
+
+  #[test]
+  fn json_missing_and_malformed() {
+    let mut e = KeyExtractor::json("/id");
+    assert_eq!(None, e.extract(r#"{"name": "John"}"#));
+    assert_eq!(None, e.extract(r#"{"id": "#));
+    assert_eq!(None, e.extract("plain text"));
+    assert_eq!(1, e.missing());
+    assert_eq!(2, e.malformed());
+  }
+
+  #[test]
+  fn regex_first_group_by_default() {
+    let mut e = KeyExtractor::regex(r#""GET ([^ ]+)"#, None, None).unwrap();
+    assert_eq!(Some("/index.html"), e.extract(r#"127.0.0.1 "GET /index.html HTTP/1.1" 200"#));
+  }
+
+  #[test]
+  fn regex_whole_match_without_groups() {
+    let mut e = KeyExtractor::regex(r"\d+", None, None).unwrap();
+    assert_eq!(Some("404"), e.extract("status=404"));
+  }
+
+  #[test]
+  fn regex_group_by_index_and_name() {
+    let line = "user=john status=200";
+    let pattern = r"user=(?P<user>\w+) status=(?P<status>\d+)";
+    assert_eq!(Some("200"), KeyExtractor::regex(pattern, Some("2"), None).unwrap().extract(line));
+    assert_eq!(Some("john"), KeyExtractor::regex(pattern, Some("user"), None).unwrap().extract(line));
+  }
+
+  #[test]
+  fn regex_unknown_group() {
+    assert!(KeyExtractor::regex(r"(\d+)", Some("2"), None).is_err());
+    assert!(KeyExtractor::regex(r"(\d+)", Some("name"), None).is_err());
+    assert!(KeyExtractor::regex(r"(", None, None).is_err());
+  }
+
+  #[test]
+  fn regex_unmatched_lines() {
+    let mut skipping = KeyExtractor::regex(r"\d+", None, None).unwrap();
+    assert_eq!(None, skipping.extract("foo"));
+    assert_eq!(1, skipping.unmatched());
+
+    let mut bucket = KeyExtractor::regex(r"\d+", None, Some("other")).unwrap();
+    assert_eq!(Some("other"), bucket.extract("foo"));
+    assert_eq!(1, bucket.unmatched());
+  }
+
+  #[test]
+  fn weights() {
+    let mut e = WeightExtractor::field(" ", 2);
+    assert_eq!(Some(42), e.extract("foo 42"));
+    assert_eq!(Some(0), e.extract("foo 0 bar"));
+    assert_eq!(None, e.extract("foo"));
+    assert_eq!(None, e.extract("foo -1"));
+    assert_eq!(None, e.extract("foo 1.5"));
+    assert_eq!(3, e.invalid());
+  }
+
+  #[test]
+  fn parse_field_list() {
+    assert_eq!(Ok(vec![1, 3]), parse_fields("1,3"));
+    assert_eq!(Ok(vec![2]), parse_fields("2"));
+    assert!(parse_fields("0").is_err());
+    assert!(parse_fields("1,a").is_err());
+  }
+}
@@ -0,0 +1,265 @@
+use md5;
+use mur3;
+use siphasher::sip::SipHasher24;
+use std::hash::{BuildHasher, Hash, Hasher};
+use xxhash_rust::xxh3::{self, Xxh3};
+
+/// Hash function used by sketches
+///
+/// Sketches are built from 64 bit hashes of the elements. Sketches could be merged and persisted
+/// only if they are using the same hash function, so each hash function has an identifier and a
+/// seed which are stored in sketch files.
+///
+/// Note that hashes of `Hash` implementations are not guaranteed to be stable between Rust
+/// releases. Sketches which are persisted should be built from byte representation of elements
+/// (eg. `LinearCounter::offer_bytes`).
+pub trait SketchHasher: BuildHasher + Sized {
+
+  /// Identifier of the hash function stored in sketch files
+  fn id(&self) -> u8;
+
+  /// Seed of the hash function stored in sketch files
+  fn seed(&self) -> u64 {
+    0
+  }
+
+  /// Restores hash function from the identifier and the seed stored in a sketch file
+  fn from_id(id: u8, seed: u64) -> Option<Self>;
+
+  fn hash<T: Hash + ?Sized>(&self, item: &T) -> u64 {
+    self.hash_one(item)
+  }
+
+  fn hash_bytes(&self, bytes: &[u8]) -> u64 {
+    let mut hasher = self.build_hasher();
+    hasher.write(bytes);
+    hasher.finish()
+  }
+
+  /// Returns `true` if both hash functions produce the same hashes
+  fn same_as(&self, other: &Self) -> bool {
+    self.id() == other.id() && self.seed() == other.seed()
+  }
+}
+
+/// Hash functions supported by sketches
+///
+/// MD5 is much slower than other functions and is kept for compatibility. XXH3 is used by default.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HashFunction {
+
+  /// First 8 bytes of MD5 digest
+  Md5,
+
+  /// 64 bit [XXH3](https://github.com/Cyan4973/xxHash)
+  Xxh3 { seed: u64 },
+
+  /// First half of 128 bit x64 variant of [MurmurHash3](https://github.com/aappleby/smhasher)
+  Murmur3 { seed: u32 },
+
+  /// [SipHash 2-4](https://www.aumasson.jp/siphash/) keyed by the seed. Seed should be kept
+  /// secret if the stream could be crafted to produce collisions.
+  SipHash { seed: u64 }
+}
+
+/// Names of hash functions accepted by `HashFunction::from_name`
+pub const HASH_FUNCTIONS: &[&str] = &["xxh3", "murmur3", "siphash", "md5"];
+
+pub const MD5_ID: u8 = 0;
+const XXH3_ID: u8 = 1;
+const MURMUR3_ID: u8 = 2;
+const SIPHASH_ID: u8 = 3;
+
+impl HashFunction {
+
+  /// Creates a hash function by its name (as listed in `HASH_FUNCTIONS`)
+  pub fn from_name(name: &str, seed: u64) -> Result<HashFunction, String> {
+    match name {
+      "md5" if seed != 0 => Err("MD5 doesn't support seed".to_string()),
+      "md5" => Ok(HashFunction::Md5),
+      "xxh3" => Ok(HashFunction::Xxh3 { seed }),
+      "murmur3" if seed > u64::from(u32::MAX) => Err("MurmurHash3 seed should fit 32 bits".to_string()),
+      "murmur3" => Ok(HashFunction::Murmur3 { seed: seed as u32 }),
+      "siphash" => Ok(HashFunction::SipHash { seed }),
+      _ => Err(format!("Unknown hash function: {}", name))
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match *self {
+      HashFunction::Md5 => "md5",
+      HashFunction::Xxh3 { .. } => "xxh3",
+      HashFunction::Murmur3 { .. } => "murmur3",
+      HashFunction::SipHash { .. } => "siphash"
+    }
+  }
+}
+
+impl Default for HashFunction {
+  fn default() -> Self {
+    HashFunction::Xxh3 { seed: 0 }
+  }
+}
+
+/// State of a `HashFunction` for a single element
+pub enum HashState {
+  Md5(md5::Context),
+  Xxh3(Box<Xxh3>),
+  Murmur3(mur3::Hasher128),
+  SipHash(SipHasher24)
+}
+
+impl BuildHasher for HashFunction {
+
+  type Hasher = HashState;
+
+  fn build_hasher(&self) -> HashState {
+    match *self {
+      HashFunction::Md5 => HashState::Md5(md5::Context::new()),
+      HashFunction::Xxh3 { seed } => HashState::Xxh3(Box::new(Xxh3::with_seed(seed))),
+      HashFunction::Murmur3 { seed } => HashState::Murmur3(mur3::Hasher128::with_seed(seed)),
+      HashFunction::SipHash { seed } => HashState::SipHash(SipHasher24::new_with_keys(seed, 0))
+    }
+  }
+}
+
+impl SketchHasher for HashFunction {
+
+  fn id(&self) -> u8 {
+    match *self {
+      HashFunction::Md5 => MD5_ID,
+      HashFunction::Xxh3 { .. } => XXH3_ID,
+      HashFunction::Murmur3 { .. } => MURMUR3_ID,
+      HashFunction::SipHash { .. } => SIPHASH_ID
+    }
+  }
+
+  fn seed(&self) -> u64 {
+    match *self {
+      HashFunction::Md5 => 0,
+      HashFunction::Xxh3 { seed } | HashFunction::SipHash { seed } => seed,
+      HashFunction::Murmur3 { seed } => u64::from(seed)
+    }
+  }
+
+  fn from_id(id: u8, seed: u64) -> Option<HashFunction> {
+    match id {
+      MD5_ID if seed == 0 => Some(HashFunction::Md5),
+      XXH3_ID => Some(HashFunction::Xxh3 { seed }),
+      MURMUR3_ID if seed <= u64::from(u32::MAX) => Some(HashFunction::Murmur3 { seed: seed as u32 }),
+      SIPHASH_ID => Some(HashFunction::SipHash { seed }),
+      _ => None
+    }
+  }
+
+  /// Hashes bytes without creating intermediate hasher state
+  fn hash_bytes(&self, bytes: &[u8]) -> u64 {
+    match *self {
+      HashFunction::Md5 => digest_prefix(&md5::compute(bytes)),
+      HashFunction::Xxh3 { seed } => xxh3::xxh3_64_with_seed(bytes, seed),
+      HashFunction::Murmur3 { seed } => mur3::murmurhash3_x64_128(bytes, seed).0,
+      HashFunction::SipHash { seed } => SipHasher24::new_with_keys(seed, 0).hash(bytes)
+    }
+  }
+}
+
+impl Hasher for HashState {
+
+  fn write(&mut self, bytes: &[u8]) {
+    match *self {
+      HashState::Md5(ref mut context) => context.consume(bytes),
+      HashState::Xxh3(ref mut hasher) => hasher.write(bytes),
+      HashState::Murmur3(ref mut hasher) => hasher.write(bytes),
+      HashState::SipHash(ref mut hasher) => hasher.write(bytes)
+    }
+  }
+
+  fn finish(&self) -> u64 {
+    match *self {
+      HashState::Md5(context) => digest_prefix(&context.compute()),
+      HashState::Xxh3(ref hasher) => hasher.digest(),
+      HashState::Murmur3(ref hasher) => hasher.finish128().0,
+      HashState::SipHash(ref hasher) => hasher.finish()
+    }
+  }
+}
+
+/// First 8 bytes of a digest as a big endian number
+fn digest_prefix(digest: &md5::Digest) -> u64 {
+  digest[..8].iter().fold(0, |hash, byte| (hash << 8) | u64::from(*byte))
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn all() -> Vec<HashFunction> {
+    vec![
+      HashFunction::Md5,
+      HashFunction::Xxh3 { seed: 1 },
+      HashFunction::Murmur3 { seed: 1 },
+      HashFunction::SipHash { seed: 1 }
+    ]
+  }
+
+  #[test]
+  fn md5_uses_first_bytes_of_digest() {
+    // MD5 of an empty string is d41d8cd98f00b204e9800998ecf8427e
+    assert_eq!(0xd41d8cd98f00b204, HashFunction::Md5.hash_bytes(b""));
+  }
+
+  #[test]
+  fn known_hashes() {
+    assert_eq!(0x2d06800538d394c2, HashFunction::Xxh3 { seed: 0 }.hash_bytes(b""));
+    assert_eq!(0, HashFunction::Murmur3 { seed: 0 }.hash_bytes(b""));
+  }
+
+  #[test]
+  fn incremental_hashing_is_consistent() {
+    for function in all() {
+      let mut hasher = function.build_hasher();
+      hasher.write(b"hello ");
+      hasher.write(b"world");
+      assert_eq!(function.hash_bytes(b"hello world"), hasher.finish(), "{:?}", function);
+    }
+  }
+
+  #[test]
+  fn seed_changes_hashes() {
+    for name in &["xxh3", "murmur3", "siphash"] {
+      let a = HashFunction::from_name(name, 1).unwrap();
+      let b = HashFunction::from_name(name, 2).unwrap();
+      assert_ne!(a.hash_bytes(b"foo"), b.hash_bytes(b"foo"), "{}", name);
+    }
+  }
+
+  #[test]
+  fn hash_of_items() {
+    for function in all() {
+      assert_eq!(function.hash(&42u64), function.hash(&42u64));
+      assert_ne!(function.hash(&42u64), function.hash(&43u64));
+    }
+  }
+
+  #[test]
+  fn restore_from_id() {
+    for function in all() {
+      assert_eq!(Some(function), HashFunction::from_id(function.id(), function.seed()));
+    }
+    assert_eq!(None, HashFunction::from_id(42, 0));
+    assert_eq!(None, HashFunction::from_id(MURMUR3_ID, u64::MAX));
+  }
+
+  #[test]
+  fn from_name() {
+    assert_eq!(Ok(HashFunction::SipHash { seed: 7 }), HashFunction::from_name("siphash", 7));
+    assert_eq!(Ok(HashFunction::Md5), HashFunction::from_name("md5", 0));
+    assert!(HashFunction::from_name("md5", 1).is_err());
+    assert!(HashFunction::from_name("murmur3", u64::MAX).is_err());
+    assert!(HashFunction::from_name("crc32", 0).is_err());
+    for name in HASH_FUNCTIONS {
+      assert_eq!(*name, HashFunction::from_name(name, 0).unwrap().name());
+    }
+  }
+}
@@ -0,0 +1,306 @@
+use error::MergeError;
+use bounds::Bounds;
+use hash::{SketchHasher, HashFunction};
+use std::hash::Hash;
+use sketch_file::{self, SketchFile, SketchKind, Header};
+use std::io::{self, Read, Write};
+use std::mem::size_of;
+
+/// HyperLogLog cardinality estimator
+///
+/// Each incoming item is hashed and the first `precision` bits of a hash are used to select one of
+/// `2^precision` registers. Register stores the maximum position of the leftmost 1-bit seen in the
+/// rest of the hash. Estimate is a bias corrected harmonic mean of all registers.
+///
+/// Comparing to `LinearCounter` memory requirements of HyperLogLog are growing only as `log(log(n))`,
+/// so it can be used for streams with very large number of unique values. Standard error of the
+/// estimate is `1.04 / sqrt(2^precision)`.
+///
+/// Detailed explanation of the algorithm: [HyperLogLog: the analysis of a near-optimal cardinality
+/// estimation algorithm](http://algo.inria.fr/flajolet/Publications/FlFuGaMe07.pdf)
+pub struct HyperLogLog<H = HashFunction> {
+  registers: Vec<u8>,
+  precision: u8,
+  hasher: H
+}
+
+pub const MIN_PRECISION: u8 = 4;
+pub const MAX_PRECISION: u8 = 18;
+
+/// Calculates the smallest precision with relative standard error not exceeding `error`. Returns
+/// `None` if such an error requires precision larger than `MAX_PRECISION`.
+pub fn precision_for_error(error: f64) -> Option<u8> {
+  assert!(error > 0.0 && error < 1.0, "Relative error should be between 0 and 1");
+  (MIN_PRECISION..=MAX_PRECISION)
+    .find(|p| 1.04 / f64::from(1u32 << p).sqrt() <= error)
+}
+
+/// Calculates the largest precision of an estimator fitting into given number of bytes. Returns
+/// `None` if even the smallest estimator doesn't fit.
+pub fn precision_for_memory(bytes: usize) -> Option<u8> {
+  (MIN_PRECISION..=MAX_PRECISION).rev()
+    .find(|p| memory_for_precision(*p) <= bytes)
+}
+
+/// Number of bytes used by an estimator of given precision
+pub fn memory_for_precision(precision: u8) -> usize {
+  size_of::<HyperLogLog>() + (1 << precision)
+}
+
+impl HyperLogLog<HashFunction> {
+
+  /// Creates estimator with `2^precision` registers (one byte each)
+  pub fn new(precision: u8) -> HyperLogLog<HashFunction> {
+    Self::with_hasher(precision, HashFunction::default())
+  }
+}
+
+impl<H: SketchHasher> HyperLogLog<H> {
+
+  pub fn with_hasher(precision: u8, hasher: H) -> HyperLogLog<H> {
+    if !(MIN_PRECISION..=MAX_PRECISION).contains(&precision) {
+      panic!("Precision should be between {} and {}", MIN_PRECISION, MAX_PRECISION)
+    }
+    HyperLogLog { registers: vec![0; 1 << precision], precision, hasher }
+  }
+
+  pub fn hasher(&self) -> &H {
+    &self.hasher
+  }
+
+  pub fn precision(&self) -> u8 {
+    self.precision
+  }
+
+  /// Updates an estimator with given item
+  pub fn offer<T: Hash + ?Sized>(&mut self, item: &T) {
+    let hash = self.hasher.hash(item);
+    self.offer_hash(hash);
+  }
+
+  /// Updates an estimator with raw bytes of an item
+  pub fn offer_bytes(&mut self, bytes: &[u8]) {
+    let hash = self.hasher.hash_bytes(bytes);
+    self.offer_hash(hash);
+  }
+
+  /// Updates an estimator with already computed hash of an item. Hash should be computed using
+  /// the hasher of this estimator.
+  pub fn offer_hash(&mut self, hash: u64) {
+    let idx = (hash >> (64 - self.precision)) as usize;
+    let rest = hash << self.precision;
+    let max_rank = 64 - self.precision + 1;
+    let rank = (rest.leading_zeros() as u8 + 1).min(max_rank);
+
+    if self.registers[idx] < rank {
+      self.registers[idx] = rank;
+    }
+  }
+
+  /// Merges other estimator into this one
+  ///
+  /// After merging the estimator estimates the number of unique elements in the union of both
+  /// streams. Estimators should have the same precision and hash function.
+  pub fn merge(&mut self, other: &HyperLogLog<H>) -> Result<(), MergeError> {
+    if self.precision != other.precision {
+      let (expected, actual) = (self.precision as usize, other.precision as usize);
+      return Err(MergeError::SizeMismatch { expected, actual });
+    }
+    if !self.hasher.same_as(&other.hasher) {
+      return Err(MergeError::HashMismatch);
+    }
+    for (register, other_register) in self.registers.iter_mut().zip(&other.registers) {
+      *register = (*register).max(*other_register);
+    }
+    Ok(())
+  }
+
+  /// Estimates a number of unique elements given to the `offer` method
+  pub fn estimate(&self) -> u64 {
+    let m = self.registers.len() as f64;
+    let sum: f64 = self.registers.iter()
+      .map(|r| 2f64.powi(-i32::from(*r)))
+      .sum();
+    let raw = self.alpha() * m * m / sum;
+
+    let zeros = self.registers.iter().filter(|r| **r == 0).count();
+    if raw <= 2.5 * m && zeros > 0 {
+      // Small range correction (linear counting over registers)
+      (m * (m / zeros as f64).ln()).round() as u64
+    } else {
+      raw.round() as u64
+    }
+  }
+
+  /// Relative standard error of the estimate
+  pub fn standard_error(&self) -> f64 {
+    1.04 / (self.registers.len() as f64).sqrt()
+  }
+
+  /// Estimates a number of unique elements with a confidence interval (eg. 0.95). Estimate is
+  /// assumed to be normally distributed with the standard error `1.04 / sqrt(m)`.
+  pub fn estimate_with_bounds(&self, confidence: f64) -> Bounds {
+    Bounds::normal(self.estimate(), self.standard_error(), confidence)
+  }
+
+  /// Writes the state of the estimator in the sketch file format
+  pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    let header = Header {
+      kind: SketchKind::HyperLogLog,
+      size: u64::from(self.precision),
+      hash: self.hasher.id(),
+      seed: self.hasher.seed()
+    };
+    SketchFile::new(header, self.registers.clone()).write_to(w)
+  }
+
+  /// Reads estimator previously written with `write_to`
+  pub fn read_from<R: Read>(r: &mut R) -> io::Result<HyperLogLog<H>> {
+    Self::from_sketch(SketchFile::read_from(r)?)
+  }
+
+  pub fn from_sketch(file: SketchFile) -> io::Result<HyperLogLog<H>> {
+    file.expect_kind(SketchKind::HyperLogLog)?;
+    let hasher = H::from_id(file.header.hash, file.header.seed)
+      .ok_or_else(|| sketch_file::invalid_data("Unsupported hash function"))?;
+    let precision = file.header.size;
+    if precision < u64::from(MIN_PRECISION) || precision > u64::from(MAX_PRECISION)
+      || file.payload.len() != 1 << precision {
+      return Err(sketch_file::invalid_data("Invalid HyperLogLog precision"));
+    }
+    Ok(HyperLogLog { registers: file.payload, precision: precision as u8, hasher })
+  }
+
+  /// Number of bytes used by the estimator
+  pub fn memory_usage(&self) -> usize {
+    size_of::<Self>() + self.registers.capacity()
+  }
+
+  fn alpha(&self) -> f64 {
+    match self.registers.len() {
+      16 => 0.673,
+      32 => 0.697,
+      64 => 0.709,
+      m => 0.7213 / (1.0 + 1.079 / m as f64)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn zero_hll() {
+    let hll = HyperLogLog::new(10);
+    assert_eq!(hll.estimate(), 0);
+  }
+
+  #[test]
+  fn small_cardinalities() {
+    for i in 1..10 {
+      let mut hll = HyperLogLog::new(14);
+      for j in 0..i {
+        hll.offer(&format!("str{}", j));
+      }
+      assert_eq!(hll.estimate(), i);
+    }
+  }
+
+  #[test]
+  fn duplicates_are_not_counted() {
+    let mut hll = HyperLogLog::new(14);
+    for _ in 0..100 {
+      hll.offer("foo");
+      hll.offer("bar");
+    }
+    assert_eq!(hll.estimate(), 2);
+  }
+
+  #[test]
+  fn large_cardinality() {
+    let mut hll = HyperLogLog::new(12);
+    let n = 100000;
+    for j in 0..n {
+      hll.offer(&format!("str{}", j));
+    }
+    let error = (hll.estimate() as f64 - n as f64).abs() / n as f64;
+    assert!(error < 3.0 * hll.standard_error(), "Error is too high: {}", error);
+  }
+
+  #[test]
+  fn bounds() {
+    let mut hll = HyperLogLog::new(12);
+    let n = 100000;
+    for j in 0..n {
+      hll.offer(&j);
+    }
+    let bounds = hll.estimate_with_bounds(0.99);
+    assert!(bounds.lower <= n && n <= bounds.upper, "{:?}", bounds);
+  }
+
+  #[test]
+  fn merge() {
+    let mut a = HyperLogLog::new(14);
+    let mut b = HyperLogLog::new(14);
+    for j in 0..10 {
+      a.offer(&format!("str{}", j));
+    }
+    for j in 5..15 {
+      b.offer(&format!("str{}", j));
+    }
+    a.merge(&b).unwrap();
+    assert_eq!(a.estimate(), 15);
+  }
+
+  #[test]
+  fn merge_precision_mismatch() {
+    let mut a = HyperLogLog::new(14);
+    let b = HyperLogLog::new(12);
+    assert_eq!(a.merge(&b), Err(MergeError::SizeMismatch { expected: 14, actual: 12 }));
+  }
+
+  #[test]
+  fn write_and_read() {
+    let mut hll = HyperLogLog::new(8);
+    for j in 0..50 {
+      hll.offer(&format!("str{}", j));
+    }
+    let mut bytes = vec![];
+    hll.write_to(&mut bytes).unwrap();
+
+    let restored: HyperLogLog = HyperLogLog::read_from(&mut &bytes[..]).unwrap();
+    assert_eq!(hll.precision, restored.precision);
+    assert_eq!(hll.registers, restored.registers);
+  }
+
+  #[test]
+  fn read_wrong_sketch_type() {
+    let mut bytes = vec![];
+    ::linear_counter::LinearCounter::new(10).write_to(&mut bytes).unwrap();
+    assert!(HyperLogLog::<HashFunction>::read_from(&mut &bytes[..]).is_err());
+  }
+
+  #[test]
+  #[should_panic]
+  fn precision_out_of_range() {
+    HyperLogLog::new(MAX_PRECISION + 1);
+  }
+
+  #[test]
+  fn memory() {
+    assert_eq!(None, precision_for_memory(16));
+    assert_eq!(Some(MAX_PRECISION), precision_for_memory(1 << 30));
+    let precision = precision_for_memory(20000).unwrap();
+    assert_eq!(14, precision);
+    assert!(HyperLogLog::new(precision).memory_usage() <= 20000);
+  }
+
+  #[test]
+  fn precision_for_given_error() {
+    assert_eq!(Some(14), precision_for_error(0.01));
+    assert_eq!(Some(MIN_PRECISION), precision_for_error(0.5));
+    assert_eq!(None, precision_for_error(0.001));
+  }
+}
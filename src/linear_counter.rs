@@ -1,68 +1,280 @@
-extern crate md5;
+use error::MergeError;
+use bounds::Bounds;
+use hash::{SketchHasher, HashFunction};
+use std::hash::Hash;
+use sketch_file::{self, SketchFile, SketchKind, Header};
+use std::io::{self, Read, Write};
+use std::mem::size_of;
 
 /// Linear counting structure
 ///
 /// Basically linear counter is the bit array. Each incoming item is associated with single bit
-/// using hash function.
+/// using hash function. Hash function could be chosen using `SketchHasher` type parameter, XXH3 is
+/// used by default.
 ///
 /// Detailed explanation of the algorithm: [A Linear-Time Probabilistic Counting Algorithm
 /// for Database Applications](http://dblab.kaist.ac.kr/Prof/pdf/ACM90_TODS_v15n2.pdf)
-pub struct LinearCounter {
-  buffer: Vec<u32>
+pub struct LinearCounter<H = HashFunction> {
+  buffer: Vec<u32>,
+  hasher: H
 }
 
-const MAX_BUFFER_SIZE: usize = (u32::max_value() / 8 / 4) as usize;
+/// Estimate of the linear counter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Estimate {
 
-impl LinearCounter {
+  /// Buffer is sparse enough, estimate is reliable
+  Accurate(u64),
 
-  pub fn new(size: usize) -> LinearCounter {
-    if size > MAX_BUFFER_SIZE {
-      panic!("Too much of buffer")
+  /// Buffer is almost full, so the estimate has large error and the counter could saturate soon.
+  /// Larger buffer should be used.
+  HighLoad(u64),
+
+  /// All bits of the buffer are set, so the number of unique elements could not be estimated. Value
+  /// is the lower bound (estimate of a buffer with only one bit not set).
+  Saturated(u64)
+}
+
+impl Estimate {
+
+  pub fn value(&self) -> u64 {
+    match *self {
+      Estimate::Accurate(value) | Estimate::HighLoad(value) | Estimate::Saturated(value) => value
     }
-    LinearCounter { buffer: vec![0; size] }
+  }
+}
+
+/// Whang et al. recommend to choose buffer size `m > β (e^t - t - 1)`, where `t` is a load factor,
+/// so the probability of the buffer to fill up is negligible. `β = 5` keeps this probability
+/// below 0.7%.
+const FILL_UP_BETA: f64 = 5.0;
+
+impl LinearCounter<HashFunction> {
+
+  pub fn new(size: usize) -> LinearCounter<HashFunction> {
+    Self::with_hasher(size, HashFunction::default())
+  }
+
+  /// Creates a counter with the smallest buffer which estimates up to `expected` unique elements
+  /// with given relative standard error (see `size_for_error`)
+  pub fn with_error(expected: u64, error: f64) -> LinearCounter<HashFunction> {
+    Self::with_error_and_hasher(expected, error, HashFunction::default())
+  }
+}
+
+/// Calculates the smallest buffer size (in 4 byte words) required to estimate up to `expected`
+/// unique elements with given relative standard error.
+///
+/// Buffer size `m` (in bits) should satisfy two conditions given by Whang et al. for the load
+/// factor `t = n / m`: standard error `sqrt(m (e^t - t - 1)) / n` should not exceed the target
+/// error, and `m > β (e^t - t - 1)`, so the buffer is unlikely to fill up.
+pub fn size_for_error(expected: u64, error: f64) -> usize {
+  assert!(error > 0.0 && error < 1.0, "Relative error should be between 0 and 1");
+  let n = expected.max(1) as f64;
+  let fits = |m: u64| {
+    let m = m as f64;
+    let t = n / m;
+    let x = t.exp() - t - 1.0;
+    m > FILL_UP_BETA * x && (m * x).sqrt() / n <= error
+  };
+
+  // Both conditions are monotonic in m, so the smallest size is found using binary search
+  let mut high: u64 = 32;
+  while !fits(high) {
+    high *= 2;
+  }
+  let mut low = high / 2;
+  while high - low > 1 {
+    let middle = low + (high - low) / 2;
+    if fits(middle) {
+      high = middle;
+    } else {
+      low = middle;
+    }
+  }
+  high.div_ceil(32) as usize
+}
+
+/// Calculates the largest buffer size (in 4 byte words) of a counter fitting into given number of
+/// bytes
+pub fn size_for_memory(bytes: usize) -> usize {
+  bytes.saturating_sub(size_of::<LinearCounter>()) / 4
+}
+
+impl<H: SketchHasher> LinearCounter<H> {
+
+  pub fn with_hasher(size: usize, hasher: H) -> LinearCounter<H> {
+    assert!(size > 0, "Linear counter should have at least one word");
+    LinearCounter { buffer: vec![0; size], hasher }
   }
 
-  /// Updates a counter with given string
-  pub fn offer(&mut self, s: &str) {
-    let digest = md5::compute(s);
-    let bit_idx = self.calculate_bit_idx(&digest);
+  pub fn with_error_and_hasher(expected: u64, error: f64, hasher: H) -> LinearCounter<H> {
+    Self::with_hasher(size_for_error(expected, error), hasher)
+  }
+
+  pub fn hasher(&self) -> &H {
+    &self.hasher
+  }
+
+  /// Size of the buffer in 4 byte words
+  pub fn size(&self) -> usize {
+    self.buffer.len()
+  }
+
+  /// Updates a counter with given item
+  pub fn offer<T: Hash + ?Sized>(&mut self, item: &T) {
+    let hash = self.hasher.hash(item);
+    self.offer_hash(hash);
+  }
+
+  /// Updates a counter with raw bytes of an item
+  pub fn offer_bytes(&mut self, bytes: &[u8]) {
+    let hash = self.hasher.hash_bytes(bytes);
+    self.offer_hash(hash);
+  }
+
+  /// Updates a counter with already computed hash of an item. Hash should be computed using
+  /// the hasher of this counter.
+  pub fn offer_hash(&mut self, hash: u64) {
+    let bit_idx = self.calculate_bit_idx(hash);
 
     // Use first 5 bits as bit offset and the rest as vector word (u32) offset
     let bit_offset = bit_idx & 0b11111;
-    let byte_offset = bit_idx >> 5;
+    let byte_offset = (bit_idx >> 5) as usize;
     self.buffer[byte_offset] |= 1 << bit_offset;
   }
 
+  /// Merges other counter into this one
+  ///
+  /// After merging the counter estimates the number of unique elements in the union of both
+  /// streams. Counters should have the same buffer size and hash function.
+  pub fn merge(&mut self, other: &LinearCounter<H>) -> Result<(), MergeError> {
+    if self.buffer.len() != other.buffer.len() {
+      return Err(MergeError::SizeMismatch { expected: self.buffer.len(), actual: other.buffer.len() });
+    }
+    if !self.hasher.same_as(&other.hasher) {
+      return Err(MergeError::HashMismatch);
+    }
+    for (word, other_word) in self.buffer.iter_mut().zip(&other.buffer) {
+      *word |= *other_word;
+    }
+    Ok(())
+  }
+
   /// Estimates a number of unique elemnts given to the `offer` method
-  pub fn estimate(&self) -> u32 {
-    let l: f64 = self.buffer.len() as f64;
-    let nf: f64 = l - self.population_count() as f64;
-    return (l * (l / nf).ln()).round() as u32;
+  pub fn estimate(&self) -> Estimate {
+    let m = self.bits() as f64;
+    if self.is_saturated() {
+      return Estimate::Saturated((m * m.ln()).round() as u64);
+    }
+    let t = self.load_factor();
+    let value = (m * t).round() as u64;
+    if m <= FILL_UP_BETA * (t.exp() - t - 1.0) {
+      Estimate::HighLoad(value)
+    } else {
+      Estimate::Accurate(value)
+    }
+  }
+
+  /// Estimates a number of unique elements with a confidence interval (eg. 0.95) based on the
+  /// variance given by Whang et al. Interval of a saturated counter is not bounded from above.
+  pub fn estimate_with_bounds(&self, confidence: f64) -> Bounds {
+    match self.estimate() {
+      Estimate::Saturated(value) => Bounds { lower: value, estimate: value, upper: u64::MAX },
+      estimate => Bounds::normal(estimate.value(), self.standard_error(), confidence)
+    }
+  }
+
+  /// Returns `true` if all bits of the buffer are set
+  pub fn is_saturated(&self) -> bool {
+    self.population_count() == self.bits()
+  }
+
+  /// Relative standard error of the estimate as given by Whang et al.
+  ///
+  /// Error is growing rapidly as the buffer is filling up, and it is infinite when all the bits
+  /// are set.
+  pub fn standard_error(&self) -> f64 {
+    if self.is_saturated() {
+      return f64::INFINITY;
+    }
+    let m = self.bits() as f64;
+    let t = self.load_factor();
+    if t == 0.0 {
+      return 0.0;
+    }
+    (m * (t.exp() - t - 1.0)).sqrt() / (m * t)
+  }
+
+  /// Estimated load factor (ratio of unique elements to the number of bits in the buffer)
+  fn load_factor(&self) -> f64 {
+    let m = self.bits() as f64;
+    let zeros = m - self.population_count() as f64;
+    (m / zeros).ln()
+  }
+
+  /// Number of bytes used by the counter
+  pub fn memory_usage(&self) -> usize {
+    size_of::<Self>() + self.buffer.capacity() * size_of::<u32>()
+  }
+
+  fn bits(&self) -> u64 {
+    self.buffer.len() as u64 * 32
+  }
+
+  /// Writes the state of the counter in the sketch file format
+  pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    let mut payload = Vec::with_capacity(self.buffer.len() * 4);
+    for word in &self.buffer {
+      sketch_file::write_u32(&mut payload, *word)?;
+    }
+    let header = Header {
+      kind: SketchKind::LinearCounter,
+      size: self.buffer.len() as u64,
+      hash: self.hasher.id(),
+      seed: self.hasher.seed()
+    };
+    SketchFile::new(header, payload).write_to(w)
+  }
+
+  /// Reads counter previously written with `write_to`
+  pub fn read_from<R: Read>(r: &mut R) -> io::Result<LinearCounter<H>> {
+    Self::from_sketch(SketchFile::read_from(r)?)
+  }
+
+  pub fn from_sketch(file: SketchFile) -> io::Result<LinearCounter<H>> {
+    file.expect_kind(SketchKind::LinearCounter)?;
+    let hasher = H::from_id(file.header.hash, file.header.seed)
+      .ok_or_else(|| sketch_file::invalid_data("Unsupported hash function"))?;
+    let size = file.header.size as usize;
+    if size == 0 || size.checked_mul(4) != Some(file.payload.len()) {
+      return Err(sketch_file::invalid_data("Invalid linear counter size"));
+    }
+
+    let mut payload = &file.payload[..];
+    let mut buffer = Vec::with_capacity(size);
+    for _ in 0..size {
+      buffer.push(sketch_file::read_u32(&mut payload)?);
+    }
+    Ok(LinearCounter { buffer, hasher })
   }
 
   /// Calculate bit index in the buffer linked to given hash sum
-  fn calculate_bit_idx(&self, digest: &md5::Digest) -> usize {
-    let mut num = digest[0] as usize;
-    num <<= 8;
-    num |= digest[1] as usize;
-    num <<= 8;
-    num |= digest[2] as usize;
-    num <<= 8;
-    num |= digest[3] as usize;
-    return num % (self.buffer.len() * 32);
-  }
-
-  fn population_count(&self) -> u32 {
-    let mut r: u32 = 0;
+  fn calculate_bit_idx(&self, hash: u64) -> u64 {
+    hash % self.bits()
+  }
+
+  fn population_count(&self) -> u64 {
+    let mut r: u64 = 0;
     let mut i: usize = self.buffer.len();
     loop {
       i -= 1;
-      r += pop_count(self.buffer[i]);
-      if i <= 0 {
+      r += u64::from(pop_count(self.buffer[i]));
+      if i == 0 {
         break;
       }
     }
-    return r
+    r
   }
 }
 
@@ -70,13 +282,36 @@ impl LinearCounter {
 fn pop_count(i: u32) -> u32 {
   let mut i: u32 = i - ((i >> 1) & 0x55555555);
   i = (i & 0x33333333) + ((i >> 2) & 0x33333333);
-  return (((i + (i >> 4)) & 0x0F0F0F0F).wrapping_mul(0x01010101)) >> 24;
+  (((i + (i >> 4)) & 0x0F0F0F0F).wrapping_mul(0x01010101)) >> 24
 }
 
 #[cfg(test)]
 mod tests {
 
   use super::*;
+  use std::collections::hash_map::DefaultHasher;
+  use std::hash::BuildHasher;
+
+  /// Hasher with configurable identifier
+  struct TestHasher(u8);
+
+  impl BuildHasher for TestHasher {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+      DefaultHasher::new()
+    }
+  }
+
+  impl SketchHasher for TestHasher {
+    fn id(&self) -> u8 {
+      self.0
+    }
+
+    fn from_id(id: u8, _seed: u64) -> Option<TestHasher> {
+      Some(TestHasher(id))
+    }
+  }
 
   #[test]
   fn population_count() {
@@ -100,8 +335,204 @@ mod tests {
         s.push_str(&j.to_string());
         lc.offer(&s);
       }
-      assert_eq!(lc.estimate(), i);
+      assert_eq!(lc.estimate(), Estimate::Accurate(i));
+    }
+  }
+
+  #[test]
+  fn estimate_beyond_buffer_words() {
+    // Number of unique elements is larger than the number of words, but still less than bits
+    let mut lc = LinearCounter::new(100);
+    for j in 0..1000 {
+      lc.offer(&format!("str{}", j));
+    }
+    let error = (lc.estimate().value() as f64 - 1000.0).abs() / 1000.0;
+    assert!(error < 3.0 * lc.standard_error(), "Error is too high: {}", error);
+  }
+
+  #[test]
+  fn size_for_given_error() {
+    let error = |words: usize| {
+      let (n, m) = (10000.0, words as f64 * 32.0);
+      let t = n / m;
+      (m * (t.exp() - t - 1.0)).sqrt() / n
+    };
+    let size = size_for_error(10000, 0.01);
+    assert!(error(size) <= 0.01);
+    assert!(error(size - 1) > 0.01);
+
+    // Fill up condition dominates for small error requirements
+    assert_eq!(1, size_for_error(1, 0.5));
+    assert!(size_for_error(1_000_000, 0.01) > size_for_error(1_000_000, 0.1));
+  }
+
+  #[test]
+  fn with_error() {
+    let n = 100000;
+    let mut lc = LinearCounter::with_error(n, 0.01);
+    for j in 0..n {
+      lc.offer(&j);
+    }
+    assert!(lc.standard_error() <= 0.011, "Error is too high: {}", lc.standard_error());
+    assert_eq!(Estimate::Accurate(lc.estimate().value()), lc.estimate());
+  }
+
+  #[test]
+  fn memory() {
+    let lc = LinearCounter::new(size_for_memory(1 << 20));
+    assert!(lc.memory_usage() <= 1 << 20);
+    assert!(lc.memory_usage() > (1 << 20) - 4);
+  }
+
+  #[test]
+  fn bounds() {
+    let n = 10000;
+    let mut lc = LinearCounter::new(100);
+    for j in 0..n {
+      lc.offer(&j);
+    }
+    let bounds = lc.estimate_with_bounds(0.99);
+    assert_eq!(lc.estimate().value(), bounds.estimate);
+    assert!(bounds.lower <= n && n <= bounds.upper, "{:?}", bounds);
+
+    let narrow = lc.estimate_with_bounds(0.5);
+    assert!(narrow.lower > bounds.lower && narrow.upper < bounds.upper);
+  }
+
+  #[test]
+  fn saturated_bounds() {
+    let mut lc = LinearCounter::new(1);
+    for j in 0..32 {
+      lc.offer_hash(j);
+    }
+    assert_eq!(Bounds { lower: 111, estimate: 111, upper: u64::MAX }, lc.estimate_with_bounds(0.95));
+  }
+
+  #[test]
+  fn high_load() {
+    let mut lc = LinearCounter::new(1);
+    for j in 0..31 {
+      lc.offer_hash(j);
+    }
+    assert_eq!(Estimate::HighLoad(111), lc.estimate());
+  }
+
+  #[test]
+  fn saturation() {
+    let mut lc = LinearCounter::new(1);
+    for j in 0..32 {
+      lc.offer_hash(j);
+    }
+    assert!(lc.is_saturated());
+    assert_eq!(Estimate::Saturated(111), lc.estimate());
+    assert_eq!(f64::INFINITY, lc.standard_error());
+  }
+
+  #[test]
+  fn merge() {
+    let mut a = LinearCounter::new(1000);
+    let mut b = LinearCounter::new(1000);
+    for j in 0..10 {
+      a.offer(&format!("str{}", j));
     }
+    for j in 5..15 {
+      b.offer(&format!("str{}", j));
+    }
+    a.merge(&b).unwrap();
+    assert_eq!(a.estimate().value(), 15);
+  }
+
+  #[test]
+  fn merge_size_mismatch() {
+    let mut a = LinearCounter::new(1000);
+    let b = LinearCounter::new(100);
+    assert_eq!(a.merge(&b), Err(MergeError::SizeMismatch { expected: 1000, actual: 100 }));
+  }
+
+  #[test]
+  fn merge_hash_mismatch() {
+    let mut a = LinearCounter::with_hasher(100, TestHasher(1));
+    let b = LinearCounter::with_hasher(100, TestHasher(2));
+    assert_eq!(a.merge(&b), Err(MergeError::HashMismatch));
+  }
+
+  #[test]
+  fn custom_hasher() {
+    let mut lc = LinearCounter::with_hasher(1000, TestHasher(1));
+    for j in 0..10 {
+      lc.offer(&(j, "foo"));
+    }
+    assert_eq!(lc.estimate().value(), 10);
+
+    let mut bytes = vec![];
+    lc.write_to(&mut bytes).unwrap();
+    let restored = LinearCounter::<TestHasher>::read_from(&mut &bytes[..]).unwrap();
+    assert_eq!(1, restored.hasher().id());
+  }
+
+  #[test]
+  fn read_unsupported_hash() {
+    let lc = LinearCounter::with_hasher(100, TestHasher(42));
+    let mut bytes = vec![];
+    lc.write_to(&mut bytes).unwrap();
+    assert!(LinearCounter::<HashFunction>::read_from(&mut &bytes[..]).is_err());
+  }
+
+  #[test]
+  fn write_and_read() {
+    let mut lc = LinearCounter::new(100);
+    for j in 0..50 {
+      lc.offer(&format!("str{}", j));
+    }
+    let mut bytes = vec![];
+    lc.write_to(&mut bytes).unwrap();
+
+    let restored: LinearCounter = LinearCounter::read_from(&mut &bytes[..]).unwrap();
+    assert_eq!(lc.buffer, restored.buffer);
+  }
+
+  #[test]
+  fn read_invalid_size() {
+    let header = Header { kind: SketchKind::LinearCounter, size: u64::MAX / 2, hash: 1, seed: 0 };
+    let file = SketchFile::new(header, vec![0; 4]);
+    assert!(LinearCounter::<HashFunction>::from_sketch(file).is_err());
+
+    let header = Header { kind: SketchKind::LinearCounter, size: 0, hash: 1, seed: 0 };
+    let file = SketchFile::new(header, vec![]);
+    assert!(LinearCounter::<HashFunction>::from_sketch(file).is_err());
+  }
+
+  #[test]
+  #[should_panic]
+  fn empty_lc() {
+    LinearCounter::new(0);
+  }
+
+  #[test]
+  fn bit_index_uses_whole_hash() {
+    let mut lc = LinearCounter::new(3);
+    lc.offer_hash(u64::MAX);
+    lc.offer_hash(u64::MAX - 1);
+    assert_eq!(lc.population_count(), 2);
+  }
+
+  #[test]
+  fn offer_integers() {
+    let mut lc = LinearCounter::new(1000);
+    for j in 0..10u64 {
+      lc.offer(&j);
+      lc.offer(&j);
+    }
+    assert_eq!(lc.estimate().value(), 10);
+  }
+
+  #[test]
+  fn offer_bytes() {
+    let mut lc = LinearCounter::new(1000);
+    lc.offer_bytes(b"foo");
+    lc.offer_bytes(b"foo");
+    lc.offer_bytes(&[0, 1, 2]);
+    assert_eq!(lc.estimate().value(), 2);
   }
 
   #[test]
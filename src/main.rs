@@ -1,17 +1,50 @@
 #[macro_use]
 extern crate clap;
+extern crate md5;
+#[macro_use]
+extern crate serde_json;
+extern crate regex;
+extern crate xxhash_rust;
+extern crate mur3;
+extern crate siphasher;
+extern crate hashbrown;
 pub mod linear_counter;
+pub mod hyper_log_log;
 pub mod stream_summary;
-pub mod double_linked_list;
+pub mod error;
+pub mod sketch_file;
+pub mod cardinality;
+pub mod extract;
+pub mod output;
+pub mod hash;
+pub mod units;
+pub mod memory;
+pub mod bounds;
+pub mod count_min_sketch;
+pub mod top_k;
 
-use clap::{Arg, App, SubCommand};
-use std::io;
+use clap::{Arg, ArgGroup, ArgMatches, App, SubCommand};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::fs::{self, File};
 
 use stream_summary::StreamSummary;
-use linear_counter::LinearCounter;
+use count_min_sketch::CountMinSketch;
+use top_k::{TopK, CountMinHeap};
+use linear_counter::{LinearCounter, Estimate};
+use hyper_log_log::HyperLogLog;
+use cardinality::Cardinality;
+use error::MergeError;
+use extract::{KeyExtractor, WeightExtractor};
+use output::Format;
+use hash::HashFunction;
+use sketch_file::{SketchFile, SketchKind};
 
 use std::process::exit;
 
+/// Average length of a key assumed when capacity of a stream summary is derived from the memory
+/// budget
+const ASSUMED_KEY_SIZE: usize = 32;
+
 fn build_cli() -> App<'static, 'static> {
   let top = SubCommand::with_name("top")
     .about("Estimates a top-k values in a stream")
@@ -20,7 +53,42 @@ fn build_cli() -> App<'static, 'static> {
       .short("s")
       .takes_value(true)
       .default_value("1000")
-      .help("Capacity of a stream-summary"));
+      .help("Number of monitored elements (capacity of a stream-summary or size of a heap)"))
+    .arg(Arg::with_name("algorithm")
+      .long("algorithm")
+      .short("a")
+      .takes_value(true)
+      .possible_values(top_k::ALGORITHMS)
+      .default_value("space-saving")
+      .help("Estimation algorithm. cms-heap counts elements by a count-min sketch and keeps the top in a \
+        heap. Options --query, --phi, --save and --load are supported only by space-saving"))
+    .args(&count_min_args())
+    .arg(memory_arg().conflicts_with_all(&["size", "width", "load"]))
+    .arg(weight_field_arg())
+    .arg(Arg::with_name("query")
+      .long("query")
+      .short("q")
+      .takes_value(true)
+      .multiple(true)
+      .number_of_values(1)
+      .value_name("key")
+      .help("Print estimated count and bounds of given key instead of the top. Could be given several times"))
+    .arg(top_k_arg().conflicts_with("query"))
+    .arg(Arg::with_name("phi")
+      .long("phi")
+      .takes_value(true)
+      .value_name("ratio")
+      .conflicts_with_all(&["query", "k"])
+      .help("Print heavy hitters, elements accounting for more than given ratio of the stream (eg. 1%), \
+        with their share of the stream. Elements guaranteed to be heavy hitters are marked with *"))
+    .arg(verbose_arg())
+    .arg(output_arg())
+    .args(&key_args())
+    .group(ArgGroup::with_name("fields")
+      .args(&["field", "weight-field"])
+      .multiple(true))
+    .arg(save_arg())
+    .arg(load_arg());
 
   let uniq = SubCommand::with_name("uniq")
     .about("Estimate number of unique values in a stream")
@@ -29,14 +97,384 @@ fn build_cli() -> App<'static, 'static> {
       .long("size")
       .help("Set the size of buffer (in 4 byte words)")
       .default_value("100000")
-      .takes_value(true));
+      .takes_value(true))
+    .arg(Arg::with_name("algorithm")
+      .long("algorithm")
+      .short("a")
+      .takes_value(true)
+      .possible_values(&["hll", "linear"])
+      .help("Estimation algorithm. By default linear counting is used while the buffer is sparse \
+        enough, HyperLogLog otherwise"))
+    .arg(Arg::with_name("precision")
+      .long("precision")
+      .short("p")
+      .takes_value(true)
+      .default_value("14")
+      .help("HyperLogLog precision (uses 2^precision one byte registers)"))
+    .arg(Arg::with_name("expected")
+      .long("expected")
+      .takes_value(true)
+      .value_name("count")
+      .requires("error")
+      .conflicts_with_all(&["size", "load"])
+      .help("Expected maximum number of unique values (eg. 50M). Buffer size is chosen to meet \
+        --error"))
+    .arg(Arg::with_name("error")
+      .long("error")
+      .takes_value(true)
+      .value_name("ratio")
+      .conflicts_with_all(&["precision", "load"])
+      .help("Target relative standard error (eg. 1%). HyperLogLog precision is chosen to meet it"))
+    .arg(memory_arg().conflicts_with_all(&["size", "precision", "expected", "error", "load"]))
+    .arg(confidence_arg())
+    .args(&hash_args())
+    .arg(output_arg())
+    .args(&key_args())
+    .group(ArgGroup::with_name("fields")
+      .arg("field"))
+    .arg(save_arg())
+    .arg(load_arg());
+
+  let freq = SubCommand::with_name("freq")
+    .about("Estimates counts of given values in a stream using a count-min sketch")
+    .args(&count_min_args())
+    .arg(Arg::with_name("epsilon")
+      .long("epsilon")
+      .takes_value(true)
+      .value_name("ratio")
+      .conflicts_with_all(&["width", "load"])
+      .help("Maximum overestimation of counts as a ratio of the stream length (eg. 0.1%). Width is chosen \
+        to meet it"))
+    .arg(Arg::with_name("delta")
+      .long("delta")
+      .takes_value(true)
+      .value_name("ratio")
+      .conflicts_with_all(&["depth", "load"])
+      .help("Probability of exceeding the error bound (eg. 1%). Depth is chosen to meet it"))
+    .arg(memory_arg().conflicts_with_all(&["width", "epsilon", "load"]))
+    .arg(query_arg())
+    .arg(Arg::with_name("queries")
+      .long("queries")
+      .takes_value(true)
+      .value_name("path")
+      .help("Read values to query from a file, one per line, after the stream is processed"))
+    .arg(weight_field_arg())
+    .args(&hash_args())
+    .arg(output_arg())
+    .args(&key_args())
+    .group(ArgGroup::with_name("fields")
+      .args(&["field", "weight-field"])
+      .multiple(true))
+    .arg(save_arg())
+    .arg(load_arg())
+    .group(ArgGroup::with_name("result")
+      .args(&["query", "queries", "save"])
+      .multiple(true)
+      .required(true));
+
+  let merge = SubCommand::with_name("merge")
+    .about("Merge sketches saved by other subcommands")
+    .arg(Arg::with_name("sketches")
+      .required(true)
+      .multiple(true)
+      .value_name("sketch")
+      .help("Sketch files to merge. All sketches should be of the same type and size"))
+    .arg(Arg::with_name("save")
+      .short("o")
+      .long("save")
+      .takes_value(true)
+      .value_name("path")
+      .help("Save the merged sketch to a file"))
+    .arg(Arg::with_name("print")
+      .short("p")
+      .long("print")
+      .help("Print the estimate of the merged sketch"))
+    .arg(confidence_arg())
+    .arg(top_k_arg())
+    .arg(query_arg().help("Comma separated values to print counts of (for count-min sketches)"))
+    .arg(verbose_arg())
+    .arg(output_arg())
+    .group(ArgGroup::with_name("result")
+      .args(&["save", "print"])
+      .multiple(true)
+      .required(true));
 
   App::new("Fast estimate")
     .arg(Arg::with_name("help")
       .long("help")
       .short("h")
       .help("Show this help"))
-    .subcommands(vec![top, uniq])
+    .subcommands(vec![top, uniq, freq, merge])
+}
+
+/// Options defining how keys are extracted from input lines
+fn key_args() -> Vec<Arg<'static, 'static>> {
+  vec![
+    Arg::with_name("field")
+      .short("f")
+      .long("field")
+      .takes_value(true)
+      .value_name("fields")
+      .help("Use given fields of a line as a key (eg. 1 or 1,3)"),
+    Arg::with_name("delimiter")
+      .short("d")
+      .long("delimiter")
+      .takes_value(true)
+      .requires("fields")
+      .help("Field delimiter (tab by default)"),
+    Arg::with_name("json")
+      .long("json")
+      .takes_value(true)
+      .value_name("pointer")
+      .conflicts_with("field")
+      .help("Parse lines as JSON and use a value at given JSON pointer as a key (eg. /user/id)"),
+    Arg::with_name("regex")
+      .long("regex")
+      .takes_value(true)
+      .value_name("pattern")
+      .conflicts_with_all(&["field", "json"])
+      .help("Use a capture group of a regular expression as a key. Lines not matching the \
+        expression are skipped"),
+    Arg::with_name("group")
+      .long("group")
+      .takes_value(true)
+      .value_name("N|name")
+      .requires("regex")
+      .help("Capture group to use as a key (first group or the whole match by default)"),
+    Arg::with_name("unmatched")
+      .long("unmatched")
+      .takes_value(true)
+      .value_name("key")
+      .requires("regex")
+      .help("Count lines not matching the regular expression under given key instead of skipping them")
+  ]
+}
+
+fn key_extractor(matches: &ArgMatches) -> KeyExtractor {
+  match matches.value_of("field") {
+    Some(fields) => {
+      let fields = extract::parse_fields(fields).unwrap_or_else(|e| exit_with_error(&e));
+      KeyExtractor::fields(delimiter(matches), fields)
+    },
+    None => match (matches.value_of("json"), matches.value_of("regex")) {
+      (Some(pointer), _) => KeyExtractor::json(pointer),
+      (None, Some(pattern)) =>
+        KeyExtractor::regex(pattern, matches.value_of("group"), matches.value_of("unmatched"))
+          .unwrap_or_else(|e| exit_with_error(&e)),
+      (None, None) => KeyExtractor::line()
+    }
+  }
+}
+
+fn delimiter<'a>(matches: &'a ArgMatches) -> &'a str {
+  match matches.value_of("delimiter") {
+    Some("\\t") | None => "\t",
+    Some(delimiter) => delimiter
+  }
+}
+
+/// Extractor of `--weight-field` if present
+fn weight_extractor(matches: &ArgMatches) -> Option<WeightExtractor> {
+  // Otherwise the whole line including the weight would be used as a key
+  if matches.is_present("weight-field") && !matches.is_present("field") && !matches.is_present("regex") {
+    exit_with_error("--weight-field requires --field or --regex to select the key");
+  }
+  matches.value_of("weight-field").map(|field| match field.parse::<usize>() {
+    Ok(field) if field > 0 => WeightExtractor::field(delimiter(matches), field),
+    _ => exit_with_error(&format!("Invalid field number: '{}'", field))
+  })
+}
+
+/// Reports lines skipped by the extractor
+fn report_skipped(extractor: &KeyExtractor) {
+  if extractor.missing() > 0 {
+    eprintln!("warning: {} lines skipped because of missing fields", extractor.missing());
+  }
+  if extractor.unmatched() > 0 {
+    eprintln!("warning: {} lines didn't match the regular expression", extractor.unmatched());
+  }
+  if extractor.malformed() > 0 {
+    eprintln!("warning: {} malformed lines skipped", extractor.malformed());
+  }
+}
+
+fn report_invalid_weights(weights: &Option<WeightExtractor>) {
+  if let Some(invalid) = weights.as_ref().map(WeightExtractor::invalid).filter(|n| *n > 0) {
+    eprintln!("warning: {} lines skipped because of missing or invalid weights", invalid);
+  }
+}
+
+fn report_saturation(counter: &Cardinality) {
+  match counter.estimate() {
+    Estimate::HighLoad(_) =>
+      eprintln!("warning: linear counter is almost full and the estimate is inaccurate. Increase --size \
+        or use --algorithm hll"),
+    Estimate::Saturated(_) =>
+      eprintln!("warning: linear counter is saturated, estimate is only a lower bound. Increase --size \
+        or use --algorithm hll"),
+    Estimate::Accurate(_) => {}
+  }
+}
+
+fn verbose_arg() -> Arg<'static, 'static> {
+  Arg::with_name("verbose")
+    .short("v")
+    .long("verbose")
+    .help("Print count, lower bound and maximum overestimation of each element. Elements \
+      guaranteed to be in the top are marked with *")
+}
+
+/// Dimensions of a count-min sketch
+fn count_min_args() -> Vec<Arg<'static, 'static>> {
+  vec![
+    Arg::with_name("width")
+      .long("width")
+      .short("w")
+      .takes_value(true)
+      .default_value("2719")
+      .help("Number of counters in each row of a count-min sketch. Counts are overestimated by at most \
+        e/width of the stream length"),
+    Arg::with_name("depth")
+      .long("depth")
+      .takes_value(true)
+      .default_value("5")
+      .help("Number of rows of a count-min sketch. Error bound is exceeded with probability e^-depth")
+  ]
+}
+
+fn weight_field_arg() -> Arg<'static, 'static> {
+  Arg::with_name("weight-field")
+    .long("weight-field")
+    .takes_value(true)
+    .value_name("N")
+    .conflicts_with("json")
+    .help("Add the value of given field (an integer, eg. bytes transferred) to the count of the key \
+      instead of counting occurrences. Fields are split by --delimiter. Requires --field or --regex to \
+      select the key")
+}
+
+fn hash_args() -> Vec<Arg<'static, 'static>> {
+  vec![
+    Arg::with_name("hash")
+      .long("hash")
+      .takes_value(true)
+      .possible_values(hash::HASH_FUNCTIONS)
+      .default_value("xxh3")
+      .help("Hash function. Sketches could be merged only if they are using the same hash function"),
+    Arg::with_name("seed")
+      .long("seed")
+      .takes_value(true)
+      .default_value("0")
+      .help("Seed of the hash function")
+  ]
+}
+
+fn hash_function(matches: &ArgMatches) -> HashFunction {
+  // Checked here rather than by clap, which treats default values as present when checking
+  // conflicts, so --load would conflict with the default hash function
+  let hash_given = matches.occurrences_of("hash") > 0 || matches.occurrences_of("seed") > 0;
+  if hash_given && matches.is_present("load") {
+    exit_with_error("--hash and --seed could not be used with --load, hash function is stored in the sketch");
+  }
+  let seed = value_t!(matches, "seed", u64).unwrap_or_else(|e| e.exit());
+  HashFunction::from_name(matches.value_of("hash").unwrap(), seed)
+    .unwrap_or_else(|e| exit_with_error(&e))
+}
+
+fn query_arg() -> Arg<'static, 'static> {
+  Arg::with_name("query")
+    .long("query")
+    .short("q")
+    .takes_value(true)
+    .multiple(true)
+    .number_of_values(1)
+    .value_name("values")
+    .help("Comma separated values to print counts of. Could be given several times")
+}
+
+/// Values given by `--query` options and read from `--queries` file
+fn query_keys(matches: &ArgMatches) -> Vec<String> {
+  let mut keys: Vec<String> = matches.values_of("query").into_iter()
+    .flat_map(|values| values.flat_map(|keys| keys.split(',')))
+    .map(str::to_string)
+    .collect();
+  if let Some(path) = matches.value_of("queries") {
+    let queries = fs::read_to_string(path).unwrap_or_else(|e| exit_with_error(&format!("{}: {}", path, e)));
+    keys.extend(queries.lines().map(str::to_string));
+  }
+  keys
+}
+
+fn top_k_arg() -> Arg<'static, 'static> {
+  Arg::with_name("k")
+    .short("k")
+    .takes_value(true)
+    .value_name("k")
+    .help("Print only k elements with the largest counts (all monitored elements by default)")
+}
+
+/// Parses `-k` option, defaults to all the monitored elements
+fn top_k(matches: &ArgMatches, capacity: usize) -> usize {
+  if matches.is_present("k") {
+    value_t!(matches, "k", usize).unwrap_or_else(|e| e.exit())
+  } else {
+    capacity
+  }
+}
+
+fn output_arg() -> Arg<'static, 'static> {
+  Arg::with_name("output")
+    .long("output")
+    .takes_value(true)
+    .possible_values(output::FORMATS)
+    .default_value("text")
+    .help("Output format")
+}
+
+fn memory_arg() -> Arg<'static, 'static> {
+  Arg::with_name("memory")
+    .short("m")
+    .long("memory")
+    .takes_value(true)
+    .value_name("bytes")
+    .help("Memory budget (eg. 64MiB). Size of the sketch is chosen to fit into it")
+}
+
+/// Parses `--memory` option if present
+fn memory_budget(matches: &ArgMatches) -> Option<usize> {
+  matches.value_of("memory")
+    .map(|memory| units::parse_bytes(memory).unwrap_or_else(|e| exit_with_error(&e)) as usize)
+}
+
+fn confidence_arg() -> Arg<'static, 'static> {
+  Arg::with_name("confidence")
+    .long("confidence")
+    .takes_value(true)
+    .value_name("level")
+    .help("Print bounds of the confidence interval of the estimate with given confidence level \
+      (eg. 0.95 or 95%)")
+}
+
+/// Parses `--confidence` option if present
+fn confidence(matches: &ArgMatches) -> Option<f64> {
+  matches.value_of("confidence")
+    .map(|confidence| units::parse_ratio(confidence).unwrap_or_else(|e| exit_with_error(&e)))
+}
+
+fn save_arg() -> Arg<'static, 'static> {
+  Arg::with_name("save")
+    .long("save")
+    .takes_value(true)
+    .value_name("path")
+    .help("Save the sketch to a file after processing the stream")
+}
+
+fn load_arg() -> Arg<'static, 'static> {
+  Arg::with_name("load")
+    .long("load")
+    .takes_value(true)
+    .value_name("path")
+    .help("Load the sketch from a file and continue with the stream (size options are ignored)")
 }
 
 fn usage() {
@@ -52,21 +490,244 @@ fn main() {
   }
 
   if let Some(matches) = matches.subcommand_matches("uniq") {
-    let size = value_t!(matches, "size", usize).unwrap_or_else(|e| e.exit());
-    let mut lc = LinearCounter::new(size);
+    let mut size = value_t!(matches, "size", usize).unwrap_or_else(|e| e.exit());
+    let mut precision = value_t!(matches, "precision", u8).unwrap_or_else(|e| e.exit());
+    if !(hyper_log_log::MIN_PRECISION..=hyper_log_log::MAX_PRECISION).contains(&precision) {
+      exit_with_error(&format!("precision should be between {} and {}",
+        hyper_log_log::MIN_PRECISION, hyper_log_log::MAX_PRECISION));
+    }
+    if let Some(error) = matches.value_of("error") {
+      let error = units::parse_ratio(error).unwrap_or_else(|e| exit_with_error(&e));
+      if let Some(expected) = matches.value_of("expected") {
+        let expected = units::parse_count(expected).unwrap_or_else(|e| exit_with_error(&e));
+        size = linear_counter::size_for_error(expected, error);
+      }
+      precision = hyper_log_log::precision_for_error(error).unwrap_or_else(||
+        exit_with_error(&format!("{} error requires HyperLogLog precision larger than {}", error,
+          hyper_log_log::MAX_PRECISION)));
+    }
+    if let Some(memory) = memory_budget(matches) {
+      let too_small = || exit_with_error("memory budget is too small");
+      match matches.value_of("algorithm") {
+        Some("linear") => size = linear_counter::size_for_memory(memory),
+        Some("hll") => precision = hyper_log_log::precision_for_memory(memory).unwrap_or_else(too_small),
+        _ => {
+          // HyperLogLog is used only when the linear counter is overloaded, so most of the memory
+          // is given to the linear counter
+          precision = hyper_log_log::precision_for_memory(memory / 16).unwrap_or_else(too_small);
+          size = linear_counter::size_for_memory(memory - hyper_log_log::memory_for_precision(precision));
+        }
+      }
+      if size == 0 {
+        too_small();
+      }
+    }
+    if size == 0 && matches.value_of("algorithm") != Some("hll") {
+      exit_with_error("size should be positive");
+    }
 
-    stdin_line_loop(|line| lc.offer(line));
+    let hash = hash_function(matches);
 
-    println!("{:}", lc.estimate())
+    let mut counter = match (matches.value_of("load"), matches.value_of("algorithm")) {
+      (Some(path), _) => Cardinality::from_sketch(load_sketch(path))
+        .unwrap_or_else(|e| exit_with_error(&format!("{}: {}", path, e))),
+      (None, Some("linear")) => Cardinality::Linear(LinearCounter::with_hasher(size, hash)),
+      (None, Some("hll")) => Cardinality::HyperLogLog(HyperLogLog::with_hasher(precision, hash)),
+      (None, _) => Cardinality::Auto(LinearCounter::with_hasher(size, hash),
+        HyperLogLog::with_hasher(precision, hash))
+    };
+
+    let format = value_t!(matches, "output", Format).unwrap_or_else(|e| e.exit());
+    let mut extractor = key_extractor(matches);
+    let lines = stdin_line_loop(|line| if let Some(key) = extractor.extract(line) {
+      counter.offer(key)
+    });
+    report_skipped(&extractor);
+
+    if let Some(path) = matches.value_of("save") {
+      save_sketch(path, |w| counter.write_to(w));
+    }
+
+    report_saturation(&counter);
+    let confidence = confidence(matches);
+    print_result(|w| output::write_cardinality(w, format, &counter, Some(lines), confidence));
 
   } else if let Some(matches) = matches.subcommand_matches("top") {
-    let size = value_t!(matches, "size", usize).unwrap_or_else(|e| e.exit());
-    let mut summary = StreamSummary::with_capacity(size);
+    if matches.value_of("algorithm") == Some("cms-heap") {
+      for option in &["query", "phi", "save", "load"] {
+        if matches.is_present(option) {
+          exit_with_error(&format!("--{} is supported only by space-saving algorithm", option));
+        }
+      }
+      let mut size = value_t!(matches, "size", usize).unwrap_or_else(|e| e.exit());
+      let mut width = value_t!(matches, "width", usize).unwrap_or_else(|e| e.exit());
+      let depth = value_t!(matches, "depth", usize).unwrap_or_else(|e| e.exit());
+      let memory = memory_budget(matches);
+      if let Some(memory) = memory {
+        // Accuracy of counts is defined by the sketch, so most of the memory is given to it
+        size = CountMinHeap::capacity_for_memory(memory / 4, ASSUMED_KEY_SIZE);
+        let heap_memory = CountMinHeap::memory_for_capacity(size, ASSUMED_KEY_SIZE);
+        width = count_min_sketch::width_for_memory(memory.saturating_sub(heap_memory), depth);
+        if size == 0 || width == 0 {
+          exit_with_error("memory budget is too small");
+        }
+      }
+      if size == 0 || width == 0 || depth == 0 {
+        exit_with_error("size, width and depth should be positive");
+      }
+      let mut heap = CountMinHeap::new(size, width, depth, HashFunction::default());
+
+      let format = value_t!(matches, "output", Format).unwrap_or_else(|e| e.exit());
+      let lines = offer_stream(matches, &mut heap);
+      warn_memory_overrun(&heap, memory);
+      let verbose = matches.is_present("verbose");
+      let k = top_k(matches, size);
+      print_result(|w| output::write_top(w, format, &heap, k, Some(lines), verbose));
+      return;
+    }
+
+    let memory = memory_budget(matches);
+    let size = match memory {
+      Some(memory) => StreamSummary::<String>::capacity_for_memory(memory, ASSUMED_KEY_SIZE),
+      None => value_t!(matches, "size", usize).unwrap_or_else(|e| e.exit())
+    };
+    if size == 0 {
+      match memory {
+        Some(_) => exit_with_error("memory budget is too small"),
+        None => exit_with_error("size should be positive")
+      }
+    }
+    let mut summary = match matches.value_of("load") {
+      Some(path) => StreamSummary::from_sketch(load_sketch(path))
+        .unwrap_or_else(|e| exit_with_error(&format!("{}: {}", path, e))),
+      None => StreamSummary::with_capacity(size)
+    };
+
+    let format = value_t!(matches, "output", Format).unwrap_or_else(|e| e.exit());
+    let lines = offer_stream(matches, &mut summary);
+
+    if let Some(path) = matches.value_of("save") {
+      save_sketch(path, |w| summary.write_to(w));
+    }
+
+    warn_memory_overrun(&summary, memory);
+
+    let phi = matches.value_of("phi")
+      .map(|phi| units::parse_ratio(phi).unwrap_or_else(|e| exit_with_error(&e)));
+    match (matches.values_of("query"), phi) {
+      (Some(keys), _) => {
+        let keys: Vec<&str> = keys.collect();
+        print_result(|w| output::write_counts(w, format, &summary, &keys, Some(lines)));
+      },
+      (None, Some(phi)) =>
+        print_result(|w| output::write_heavy_hitters(w, format, &summary, phi, Some(lines))),
+      (None, None) => {
+        let verbose = matches.is_present("verbose");
+        let k = top_k(matches, summary.capacity());
+        print_result(|w| output::write_top(w, format, &summary, k, Some(lines), verbose));
+      }
+    }
+
+  } else if let Some(matches) = matches.subcommand_matches("freq") {
+    let mut width = value_t!(matches, "width", usize).unwrap_or_else(|e| e.exit());
+    let mut depth = value_t!(matches, "depth", usize).unwrap_or_else(|e| e.exit());
+    if let Some(epsilon) = matches.value_of("epsilon") {
+      let epsilon = units::parse_ratio(epsilon).unwrap_or_else(|e| exit_with_error(&e));
+      width = count_min_sketch::width_for_error(epsilon);
+    }
+    if let Some(delta) = matches.value_of("delta") {
+      let delta = units::parse_ratio(delta).unwrap_or_else(|e| exit_with_error(&e));
+      depth = count_min_sketch::depth_for_error(delta);
+    }
+    if let Some(memory) = memory_budget(matches) {
+      width = count_min_sketch::width_for_memory(memory, depth);
+      if width == 0 {
+        exit_with_error("memory budget is too small");
+      }
+    }
+    if width == 0 || depth == 0 {
+      exit_with_error("width and depth should be positive");
+    }
+
+    let hash = hash_function(matches);
+    let mut sketch = match matches.value_of("load") {
+      Some(path) => CountMinSketch::from_sketch(load_sketch(path))
+        .unwrap_or_else(|e| exit_with_error(&format!("{}: {}", path, e))),
+      None => CountMinSketch::with_hasher(width, depth, hash)
+    };
+
+    let format = value_t!(matches, "output", Format).unwrap_or_else(|e| e.exit());
+    let mut extractor = key_extractor(matches);
+    let mut weights = weight_extractor(matches);
+    let lines = stdin_line_loop(|line| match weights {
+      Some(ref mut weights) => if let Some(weight) = weights.extract(line) {
+        if let Some(key) = extractor.extract(line) {
+          sketch.offer_weighted_bytes(key.as_bytes(), weight);
+        }
+      },
+      None => if let Some(key) = extractor.extract(line) {
+        sketch.offer_bytes(key.as_bytes());
+      }
+    });
+    report_skipped(&extractor);
+    report_invalid_weights(&weights);
+
+    if let Some(path) = matches.value_of("save") {
+      save_sketch(path, |w| sketch.write_to(w));
+    }
+
+    let keys = query_keys(matches);
+    if !keys.is_empty() {
+      let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+      print_result(|w| output::write_frequencies(w, format, &sketch, &keys, Some(lines)));
+    }
 
-    stdin_line_loop(|line| {summary.offer(line);});
+  } else if let Some(matches) = matches.subcommand_matches("merge") {
+    let files: Vec<(&str, SketchFile)> = matches.values_of("sketches").unwrap()
+      .map(|path| (path, load_sketch(path)))
+      .collect();
 
-    for item in summary.estimate_top() {
-      println!("{:6} : {}", item.count, item.data)
+    let format = value_t!(matches, "output", Format).unwrap_or_else(|e| e.exit());
+    match files[0].1.header.kind {
+      SketchKind::StreamSummary => {
+        let merged = merge_sketches(files, StreamSummary::from_sketch, |a, b| {
+          a.merge(b);
+          Ok(())
+        });
+        if let Some(path) = matches.value_of("save") {
+          save_sketch(path, |w| merged.write_to(w));
+        }
+        if matches.is_present("print") {
+          let verbose = matches.is_present("verbose");
+          let k = top_k(matches, merged.capacity());
+          print_result(|w| output::write_top(w, format, &merged, k, None, verbose));
+        }
+      },
+      SketchKind::CountMin => {
+        let merged = merge_sketches(files, CountMinSketch::from_sketch, CountMinSketch::merge);
+        if let Some(path) = matches.value_of("save") {
+          save_sketch(path, |w| merged.write_to(w));
+        }
+        if matches.is_present("print") {
+          let keys = query_keys(matches);
+          if keys.is_empty() {
+            exit_with_error("--query is required to print a count-min sketch");
+          }
+          let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+          print_result(|w| output::write_frequencies(w, format, &merged, &keys, None));
+        }
+      },
+      _ => {
+        let merged = merge_sketches(files, Cardinality::from_sketch, Cardinality::merge);
+        if let Some(path) = matches.value_of("save") {
+          save_sketch(path, |w| merged.write_to(w));
+        }
+        if matches.is_present("print") {
+          report_saturation(&merged);
+          let confidence = confidence(matches);
+          print_result(|w| output::write_cardinality(w, format, &merged, None, confidence));
+        }
+      }
     }
 
   } else {
@@ -74,6 +735,87 @@ fn main() {
   }
 }
 
+/// Offers keys (and weights if `--weight-field` is given) of stdin lines to the top-k structure.
+/// Returns number of lines processed.
+/// Warns if the structure doesn't fit into the memory budget because of long keys
+fn warn_memory_overrun(top: &dyn TopK, memory: Option<usize>) {
+  if let Some(memory) = memory {
+    if top.memory_usage() > memory {
+      eprintln!("warning: {} uses {} bytes, which is more than the memory budget. Keys are longer than \
+        expected, use smaller --memory", top.algorithm(), top.memory_usage());
+    }
+  }
+}
+
+fn offer_stream(matches: &ArgMatches, top: &mut dyn TopK) -> u64 {
+  let mut extractor = key_extractor(matches);
+  let mut weights = weight_extractor(matches);
+  let lines = stdin_line_loop(|line| match weights {
+    Some(ref mut weights) => if let Some(weight) = weights.extract(line) {
+      if let Some(key) = extractor.extract(line) {
+        top.offer_weighted(key, weight);
+      }
+    },
+    None => if let Some(key) = extractor.extract(line) {
+      top.offer(key);
+    }
+  });
+  report_skipped(&extractor);
+  report_invalid_weights(&weights);
+  lines
+}
+
+fn print_result<F>(write: F)
+  where F: FnOnce(&mut io::StdoutLock) -> io::Result<()> {
+
+  let stdout = io::stdout();
+  let mut stdout = stdout.lock();
+  match write(&mut stdout).and_then(|_| stdout.flush()) {
+    Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => exit(0),
+    Err(e) => exit_with_error(&e.to_string()),
+    Ok(_) => {}
+  }
+}
+
+/// Merges all the sketch files into the first one
+fn merge_sketches<T, R, M>(files: Vec<(&str, SketchFile)>, read: R, merge: M) -> T
+  where R: Fn(SketchFile) -> io::Result<T>, M: Fn(&mut T, &T) -> Result<(), MergeError> {
+
+  let mut merged = None;
+  for (path, file) in files {
+    let sketch = read(file).unwrap_or_else(|e| exit_with_error(&format!("{}: {}", path, e)));
+    match merged {
+      None => merged = Some(sketch),
+      Some(ref mut merged) => merge(merged, &sketch)
+        .unwrap_or_else(|e| exit_with_error(&format!("{}: {}", path, e)))
+    }
+  }
+  merged.expect("No sketches given")
+}
+
+fn load_sketch(path: &str) -> SketchFile {
+  File::open(path)
+    .and_then(|file| SketchFile::read_from(&mut BufReader::new(file)))
+    .unwrap_or_else(|e| exit_with_error(&format!("{}: {}", path, e)))
+}
+
+fn save_sketch<F>(path: &str, write: F)
+  where F: FnOnce(&mut BufWriter<File>) -> io::Result<()> {
+
+  File::create(path)
+    .and_then(|file| {
+      let mut writer = BufWriter::new(file);
+      write(&mut writer)?;
+      writer.flush()
+    })
+    .unwrap_or_else(|e| exit_with_error(&format!("{}: {}", path, e)))
+}
+
+fn exit_with_error(message: &str) -> ! {
+  eprintln!("error: {}", message);
+  exit(1);
+}
+
 fn trim_newline(s: &mut String) {
   if s.ends_with('\n') {
     s.pop();
@@ -83,21 +825,25 @@ fn trim_newline(s: &mut String) {
   }
 }
 
-fn stdin_line_loop<F>(mut line_callback: F)
+/// Calls `line_callback` for each line of stdin. Returns number of lines processed.
+fn stdin_line_loop<F>(mut line_callback: F) -> u64
   where F: FnMut(&str) {
 
   let stdin = io::stdin();
   let mut line = String::new();
+  let mut lines = 0;
 
   loop {
     line.clear();
     match stdin.read_line(&mut line) {
-      Ok(n) if n <= 0 => break,
+      Ok(0) => break,
       Ok(_) => {
         trim_newline(&mut line);
+        lines += 1;
         line_callback(&line)
       },
       Err(e) => panic!("{:?}", e)
     }
   }
+  lines
 }
@@ -0,0 +1,109 @@
+use std::mem::size_of;
+
+/// Memory allocated on the heap by a value
+///
+/// Used to estimate memory consumption of sketches storing elements (eg. `StreamSummary`).
+pub trait HeapSize {
+
+  /// Number of bytes allocated on the heap (not including the size of the value itself)
+  fn heap_size(&self) -> usize;
+}
+
+impl HeapSize for String {
+  fn heap_size(&self) -> usize {
+    self.capacity()
+  }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+  fn heap_size(&self) -> usize {
+    self.capacity() * size_of::<T>() + self.iter().map(HeapSize::heap_size).sum::<usize>()
+  }
+}
+
+impl<A: HeapSize, B: HeapSize> HeapSize for (A, B) {
+  fn heap_size(&self) -> usize {
+    self.0.heap_size() + self.1.heap_size()
+  }
+}
+
+macro_rules! no_heap {
+  ($($t:ty),*) => {
+    $(impl HeapSize for $t {
+      fn heap_size(&self) -> usize {
+        0
+      }
+    })*
+  }
+}
+
+no_heap!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, char, bool);
+
+/// Approximate memory used by a hash table with given number of buckets
+///
+/// Hash table (`HashMap` or `hashbrown::HashTable`) stores entries of type `T` inline, plus one
+/// control byte per bucket. Number of buckets is a power of two keeping load factor under 7/8.
+fn hash_table_buckets_size<T>(buckets: usize) -> usize {
+  buckets * (size_of::<T>() + 1)
+}
+
+/// Approximate memory used by a hash table with given capacity (as returned by `capacity()`)
+pub fn hash_table_size<T>(capacity: usize) -> usize {
+  let buckets = match capacity {
+    0 => 0,
+    1..=7 => capacity + 1,
+    _ => capacity / 7 * 8
+  };
+  hash_table_buckets_size::<T>(buckets)
+}
+
+/// Approximate memory used by a hash table created with `with_capacity(capacity)`
+pub fn hash_table_size_for_capacity<T>(capacity: usize) -> usize {
+  let buckets = match capacity {
+    0 => 0,
+    1..=3 => 4,
+    4..=7 => 8,
+    _ => (capacity * 8 / 7).next_power_of_two()
+  };
+  hash_table_buckets_size::<T>(buckets)
+}
+
+/// Calculates the largest capacity of a structure fitting into given number of bytes, when memory
+/// used by the structure grows with capacity as given by `memory_for_capacity`
+pub fn capacity_for_memory<F: Fn(usize) -> usize>(bytes: usize, memory_for_capacity: F) -> usize {
+  let mut low = 0;
+  let mut high = bytes;
+  while high - low > 1 {
+    let middle = low + (high - low) / 2;
+    if memory_for_capacity(middle) <= bytes {
+      low = middle;
+    } else {
+      high = middle;
+    }
+  }
+  low
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+  use std::collections::HashMap;
+
+  #[test]
+  fn heap_size_of_keys() {
+    assert_eq!(0, 42u64.heap_size());
+    assert_eq!(5, String::from("hello").heap_size());
+    assert_eq!(16, vec![1u32, 2, 3, 4].heap_size());
+    assert_eq!(3, (1u8, String::from("foo")).heap_size());
+  }
+
+  #[test]
+  fn hash_table_estimation_is_consistent() {
+    for capacity in &[0, 1, 5, 100, 1000, 12345] {
+      let map: HashMap<u64, u64> = HashMap::with_capacity(*capacity);
+      assert_eq!(hash_table_size_for_capacity::<(u64, u64)>(*capacity),
+        hash_table_size::<(u64, u64)>(map.capacity()), "capacity {}", capacity);
+    }
+  }
+}
@@ -0,0 +1,546 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+use serde_json::{self, Value};
+use cardinality::{Cardinality, Estimator};
+use stream_summary::{StreamSummary, Frequency};
+use linear_counter::Estimate;
+use bounds::Bounds;
+use count_min_sketch::CountMinSketch;
+use top_k::TopK;
+
+/// Format of the results printed by subcommands
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+  Text,
+  Json,
+  Csv,
+  Tsv
+}
+
+impl FromStr for Format {
+
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "text" => Ok(Format::Text),
+      "json" => Ok(Format::Json),
+      "csv" => Ok(Format::Csv),
+      "tsv" => Ok(Format::Tsv),
+      _ => Err(format!("Unknown output format: {}", s))
+    }
+  }
+}
+
+pub const FORMATS: &[&str] = &["text", "json", "csv", "tsv"];
+
+/// Writes the result of a cardinality estimation
+///
+/// `lines` is the number of lines processed (if known). It is reported only in JSON format. If
+/// `confidence` is given, bounds of the confidence interval are reported as well.
+pub fn write_cardinality<W: Write>(w: &mut W, format: Format, counter: &Cardinality,
+                                   lines: Option<u64>, confidence: Option<f64>) -> io::Result<()> {
+  let (algorithm, parameters) = match counter.selected() {
+    Estimator::Linear(lc) => ("linear", json!({"size": lc.size(), "hash": lc.hasher().name()})),
+    Estimator::HyperLogLog(hll) => ("hll", json!({"precision": hll.precision(), "hash": hll.hasher().name()}))
+  };
+  let (estimate, status) = match counter.estimate() {
+    Estimate::Accurate(value) => (value, "accurate"),
+    Estimate::HighLoad(value) => (value, "high_load"),
+    Estimate::Saturated(value) => (value, "saturated")
+  };
+  let error = counter.standard_error();
+  let bounds = confidence.map(|c| counter.estimate_with_bounds(c));
+  // Unbounded upper bound is reported as `inf` in text, empty field in CSV/TSV and null in JSON
+  let upper = |bounds: &Bounds| if bounds.upper == u64::MAX { None } else { Some(bounds.upper) };
+
+  match format {
+    Format::Text => match (bounds, confidence) {
+      (Some(bounds), Some(confidence)) => {
+        let upper = upper(&bounds).map_or("inf".to_string(), |u| u.to_string());
+        writeln!(w, "{} ({} - {} at {}% confidence)", estimate, bounds.lower, upper, confidence * 100.0)
+      },
+      _ => writeln!(w, "{}", estimate)
+    },
+    Format::Json => {
+      let mut json = json!({
+        "algorithm": algorithm,
+        "parameters": parameters,
+        "lines": lines,
+        "estimate": estimate,
+        "status": status,
+        "standard_error": error,
+        "memory_usage": counter.memory_usage()
+      });
+      if let Some(bounds) = bounds {
+        json["confidence"] = json!(confidence);
+        json["lower_bound"] = json!(bounds.lower);
+        json["upper_bound"] = json!(upper(&bounds));
+      }
+      write_json(w, &json)
+    },
+    Format::Csv | Format::Tsv => match bounds {
+      Some(bounds) => {
+        write_row(w, format, &["algorithm", "estimate", "standard_error", "lower_bound", "upper_bound"])?;
+        let upper = upper(&bounds).map_or(String::new(), |u| u.to_string());
+        write_row(w, format, &[algorithm, &estimate.to_string(), &error.to_string(),
+          &bounds.lower.to_string(), &upper])
+      },
+      None => {
+        write_row(w, format, &["algorithm", "estimate", "standard_error"])?;
+        write_row(w, format, &[algorithm, &estimate.to_string(), &error.to_string()])
+      }
+    }
+  }
+}
+
+/// Writes at most `k` elements of estimated top of a stream
+///
+/// In text format lower bound, maximum overestimation and guarantee flag of each element are
+/// reported only if `verbose` is set. In other formats they are always reported.
+pub fn write_top<W: Write>(w: &mut W, format: Format, summary: &dyn TopK, k: usize,
+                           lines: Option<u64>, verbose: bool) -> io::Result<()> {
+  let top = summary.top(k);
+
+  match format {
+    Format::Text if verbose => {
+      writeln!(w, " count  lower  error   : value")?;
+      for item in top {
+        let guaranteed = if item.guaranteed { "*" } else { " " };
+        writeln!(w, "{:6} {:6} {:6} {} : {}", item.count, item.lower_bound, item.count - item.lower_bound,
+          guaranteed, item.data)?;
+      }
+      Ok(())
+    },
+    Format::Text => {
+      for item in top {
+        writeln!(w, "{:6} : {}", item.count, item.data)?;
+      }
+      Ok(())
+    },
+    Format::Json => {
+      let items: Vec<Value> = top.iter()
+        .map(|item| json!({
+          "value": item.data,
+          "count": item.count,
+          "lower_bound": item.lower_bound,
+          "epsilon": item.count - item.lower_bound,
+          "guaranteed": item.guaranteed
+        }))
+        .collect();
+      let parameters: serde_json::Map<String, Value> = summary.parameters().into_iter()
+        .map(|(name, value)| (name.to_string(), json!(value)))
+        .collect();
+      write_json(w, &json!({
+        "algorithm": summary.algorithm(),
+        "parameters": parameters,
+        "lines": lines,
+        "total": summary.total(),
+        "memory_usage": summary.memory_usage(),
+        "items": items
+      }))
+    },
+    Format::Csv | Format::Tsv => {
+      write_row(w, format, &["value", "count", "lower_bound", "epsilon", "guaranteed"])?;
+      for item in top {
+        write_row(w, format, &[item.data, &item.count.to_string(), &item.lower_bound.to_string(),
+          &(item.count - item.lower_bound).to_string(), &item.guaranteed.to_string()])?;
+      }
+      Ok(())
+    }
+  }
+}
+
+/// Writes elements accounting for more than `phi` fraction of the total count
+///
+/// Share of each element is reported in percents of the total count. Elements guaranteed to be
+/// heavy hitters are marked with `*` in text format.
+pub fn write_heavy_hitters<W: Write>(w: &mut W, format: Format, summary: &StreamSummary, phi: f64,
+                                     lines: Option<u64>) -> io::Result<()> {
+  let hitters = summary.heavy_hitters(phi);
+  let share = |count: u64| count as f64 * 100.0 / summary.total() as f64;
+
+  match format {
+    Format::Text => {
+      for hitter in hitters {
+        let guaranteed = if hitter.guaranteed { "*" } else { " " };
+        writeln!(w, "{:6} {:6.2}% {} : {}", hitter.item.count, share(hitter.item.count), guaranteed,
+          hitter.item.data)?;
+      }
+      Ok(())
+    },
+    Format::Json => {
+      let items: Vec<Value> = hitters.iter()
+        .map(|hitter| json!({
+          "value": hitter.item.data,
+          "count": hitter.item.count,
+          "lower_bound": hitter.item.lower_bound(),
+          "epsilon": hitter.item.epsilon,
+          "share": share(hitter.item.count),
+          "guaranteed": hitter.guaranteed
+        }))
+        .collect();
+      write_json(w, &json!({
+        "algorithm": "space-saving",
+        "parameters": {"capacity": summary.capacity()},
+        "lines": lines,
+        "total": summary.total(),
+        "phi": phi,
+        "items": items
+      }))
+    },
+    Format::Csv | Format::Tsv => {
+      write_row(w, format, &["value", "count", "lower_bound", "epsilon", "share", "guaranteed"])?;
+      for hitter in hitters {
+        let item = hitter.item;
+        write_row(w, format, &[&item.data, &item.count.to_string(), &item.lower_bound().to_string(),
+          &item.epsilon.to_string(), &share(item.count).to_string(), &hitter.guaranteed.to_string()])?;
+      }
+      Ok(())
+    }
+  }
+}
+
+/// Writes estimated counts of given elements
+///
+/// Count of an element which is not monitored by the summary is unknown (reported as `-` in text,
+/// empty field in CSV/TSV and null in JSON), only its upper bound is reported.
+pub fn write_counts<W: Write>(w: &mut W, format: Format, summary: &StreamSummary, keys: &[&str],
+                              lines: Option<u64>) -> io::Result<()> {
+  let counts: Vec<(&str, Frequency)> = keys.iter().map(|key| (*key, summary.count_of(*key))).collect();
+  let count = |frequency: &Frequency| if frequency.is_monitored() {
+    Some(frequency.bounds().estimate)
+  } else {
+    None
+  };
+
+  match format {
+    Format::Text => {
+      writeln!(w, " count  lower  upper : value")?;
+      for (key, frequency) in counts {
+        let bounds = frequency.bounds();
+        let estimate = count(&frequency).map_or("-".to_string(), |c| c.to_string());
+        writeln!(w, "{:>6} {:6} {:6} : {}", estimate, bounds.lower, bounds.upper, key)?;
+      }
+      Ok(())
+    },
+    Format::Json => {
+      let items: Vec<Value> = counts.iter()
+        .map(|&(key, ref frequency)| json!({
+          "value": key,
+          "monitored": frequency.is_monitored(),
+          "count": count(frequency),
+          "lower_bound": frequency.bounds().lower,
+          "upper_bound": frequency.bounds().upper
+        }))
+        .collect();
+      write_json(w, &json!({
+        "algorithm": "space-saving",
+        "parameters": {"capacity": summary.capacity()},
+        "lines": lines,
+        "items": items
+      }))
+    },
+    Format::Csv | Format::Tsv => {
+      write_row(w, format, &["value", "monitored", "count", "lower_bound", "upper_bound"])?;
+      for (key, frequency) in counts {
+        let bounds = frequency.bounds();
+        write_row(w, format, &[key, &frequency.is_monitored().to_string(),
+          &count(&frequency).map_or(String::new(), |c| c.to_string()), &bounds.lower.to_string(),
+          &bounds.upper.to_string()])?;
+      }
+      Ok(())
+    }
+  }
+}
+
+/// Writes estimated counts of given elements by a count-min sketch
+///
+/// Lower bound of each count holds with probability `1 - delta`. It's reported only in JSON,
+/// CSV and TSV formats.
+pub fn write_frequencies<W: Write>(w: &mut W, format: Format, sketch: &CountMinSketch, keys: &[&str],
+                                   lines: Option<u64>) -> io::Result<()> {
+  let counts: Vec<(&str, Bounds)> = keys.iter()
+    .map(|key| (*key, sketch.estimate_bytes_with_bounds(key.as_bytes())))
+    .collect();
+
+  match format {
+    Format::Text => {
+      for (key, bounds) in counts {
+        writeln!(w, "{:6} : {}", bounds.estimate, key)?;
+      }
+      Ok(())
+    },
+    Format::Json => {
+      let items: Vec<Value> = counts.iter()
+        .map(|&(key, bounds)| json!({
+          "value": key,
+          "count": bounds.estimate,
+          "lower_bound": bounds.lower
+        }))
+        .collect();
+      write_json(w, &json!({
+        "algorithm": "count-min",
+        "parameters": {"width": sketch.width(), "depth": sketch.depth(), "hash": sketch.hasher().name()},
+        "lines": lines,
+        "total": sketch.total(),
+        "epsilon": sketch.epsilon(),
+        "delta": sketch.delta(),
+        "memory_usage": sketch.memory_usage(),
+        "items": items
+      }))
+    },
+    Format::Csv | Format::Tsv => {
+      write_row(w, format, &["value", "count", "lower_bound"])?;
+      for (key, bounds) in counts {
+        write_row(w, format, &[key, &bounds.estimate.to_string(), &bounds.lower.to_string()])?;
+      }
+      Ok(())
+    }
+  }
+}
+
+fn write_json<W: Write>(w: &mut W, value: &Value) -> io::Result<()> {
+  serde_json::to_writer_pretty(&mut *w, value)?;
+  writeln!(w)
+}
+
+fn write_row<W: Write>(w: &mut W, format: Format, fields: &[&str]) -> io::Result<()> {
+  let separator = if format == Format::Tsv { "\t" } else { "," };
+  for (i, field) in fields.iter().enumerate() {
+    if i > 0 {
+      w.write_all(separator.as_bytes())?;
+    }
+    if format == Format::Tsv {
+      w.write_all(escape_tsv(field).as_bytes())?;
+    } else {
+      w.write_all(escape_csv(field).as_bytes())?;
+    }
+  }
+  writeln!(w)
+}
+
+/// Quotes CSV field as described in [RFC 4180](https://tools.ietf.org/html/rfc4180)
+fn escape_csv(field: &str) -> String {
+  if field.contains(&[',', '"', '\n', '\r'][..]) {
+    format!("\"{}\"", field.replace('"', "\"\""))
+  } else {
+    field.to_string()
+  }
+}
+
+/// Escapes characters which are not allowed in TSV fields
+fn escape_tsv(field: &str) -> String {
+  field.replace('\\', "\\\\")
+    .replace('\t', "\\t")
+    .replace('\n', "\\n")
+    .replace('\r', "\\r")
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+  use linear_counter::LinearCounter;
+
+  fn top_output(format: Format) -> String {
+    let mut summary = StreamSummary::new();
+    summary.offer("foo, \"bar\"");
+    summary.offer("foo, \"bar\"");
+    summary.offer("a\tb");
+
+    let mut out = vec![];
+    write_top(&mut out, format, &summary, 10, Some(3), false).unwrap();
+    String::from_utf8(out).unwrap()
+  }
+
+  #[test]
+  fn parse_format() {
+    assert_eq!(Ok(Format::Json), "json".parse());
+    assert!("xml".parse::<Format>().is_err());
+  }
+
+  #[test]
+  fn top_text() {
+    assert_eq!("     2 : foo, \"bar\"\n     1 : a\tb\n", top_output(Format::Text));
+  }
+
+  #[test]
+  fn top_csv() {
+    let expected = "value,count,lower_bound,epsilon,guaranteed\n\
+      \"foo, \"\"bar\"\"\",2,2,0,true\n\
+      a\tb,1,1,0,true\n";
+    assert_eq!(expected, top_output(Format::Csv));
+  }
+
+  #[test]
+  fn top_tsv() {
+    let expected = "value\tcount\tlower_bound\tepsilon\tguaranteed\n\
+      foo, \"bar\"\t2\t2\t0\ttrue\n\
+      a\\tb\t1\t1\t0\ttrue\n";
+    assert_eq!(expected, top_output(Format::Tsv));
+  }
+
+  #[test]
+  fn top_json() {
+    let json: Value = serde_json::from_str(&top_output(Format::Json)).unwrap();
+    assert_eq!("space-saving", json["algorithm"]);
+    assert_eq!(1000, json["parameters"]["capacity"]);
+    assert_eq!(3, json["lines"]);
+    assert_eq!("foo, \"bar\"", json["items"][0]["value"]);
+    assert_eq!(2, json["items"][0]["count"]);
+  }
+
+  #[test]
+  fn top_k_text() {
+    let mut summary = StreamSummary::new();
+    summary.offer("foo");
+    summary.offer("foo");
+    summary.offer("bar");
+
+    let mut out = vec![];
+    write_top(&mut out, Format::Text, &summary, 1, None, true).unwrap();
+    assert_eq!(" count  lower  error   : value\n     2      2      0 * : foo\n",
+      String::from_utf8(out).unwrap());
+  }
+
+  fn heavy_hitters_output(format: Format) -> String {
+    let mut summary = StreamSummary::with_capacity(2);
+    for key in &["foo", "foo", "foo", "bar", "baz", "foo", "foo", "baz"] {
+      summary.offer(*key);
+    }
+
+    let mut out = vec![];
+    write_heavy_hitters(&mut out, format, &summary, 0.2, Some(8)).unwrap();
+    String::from_utf8(out).unwrap()
+  }
+
+  #[test]
+  fn heavy_hitters_text() {
+    assert_eq!("     5  62.50% * : foo\n     3  37.50% * : baz\n", heavy_hitters_output(Format::Text));
+  }
+
+  #[test]
+  fn heavy_hitters_csv() {
+    let expected = "value,count,lower_bound,epsilon,share,guaranteed\n\
+      foo,5,5,0,62.5,true\n\
+      baz,3,2,1,37.5,true\n";
+    assert_eq!(expected, heavy_hitters_output(Format::Csv));
+  }
+
+  fn counts_output(format: Format) -> String {
+    let mut summary = StreamSummary::with_capacity(1);
+    summary.offer("foo");
+    summary.offer("bar");
+
+    let mut out = vec![];
+    write_counts(&mut out, format, &summary, &["bar", "foo"], Some(2)).unwrap();
+    String::from_utf8(out).unwrap()
+  }
+
+  #[test]
+  fn counts_text() {
+    let expected = " count  lower  upper : value
+     \
+      2      1      2 : bar
+     \
+      -      0      2 : foo
+";
+    assert_eq!(expected, counts_output(Format::Text));
+  }
+
+  #[test]
+  fn counts_csv() {
+    let expected = "value,monitored,count,lower_bound,upper_bound
+\
+      bar,true,2,1,2
+\
+      foo,false,,0,2
+";
+    assert_eq!(expected, counts_output(Format::Csv));
+  }
+
+  #[test]
+  fn counts_json() {
+    let json: Value = serde_json::from_str(&counts_output(Format::Json)).unwrap();
+    assert_eq!(true, json["items"][0]["monitored"]);
+    assert_eq!(2, json["items"][0]["count"]);
+    assert_eq!(Value::Null, json["items"][1]["count"]);
+    assert_eq!(2, json["items"][1]["upper_bound"]);
+  }
+
+  fn frequencies_output(format: Format) -> String {
+    let mut sketch = CountMinSketch::new(1000, 3);
+    sketch.offer_weighted_bytes(b"foo", 3);
+    sketch.offer_bytes(b"bar");
+
+    let mut out = vec![];
+    write_frequencies(&mut out, format, &sketch, &["foo", "baz"], Some(2)).unwrap();
+    String::from_utf8(out).unwrap()
+  }
+
+  #[test]
+  fn frequencies_text() {
+    assert_eq!("     3 : foo\n     0 : baz\n", frequencies_output(Format::Text));
+  }
+
+  #[test]
+  fn frequencies_json() {
+    let json: Value = serde_json::from_str(&frequencies_output(Format::Json)).unwrap();
+    assert_eq!("count-min", json["algorithm"]);
+    assert_eq!(4, json["total"]);
+    assert_eq!(3, json["items"][0]["count"]);
+    assert_eq!(2, json["items"][0]["lower_bound"]);
+  }
+
+  #[test]
+  fn cardinality_json() {
+    let mut counter = Cardinality::Linear(LinearCounter::new(100));
+    counter.offer("foo");
+
+    let mut out = vec![];
+    write_cardinality(&mut out, Format::Json, &counter, Some(1), None).unwrap();
+    let json: Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!("linear", json["algorithm"]);
+    assert_eq!(100, json["parameters"]["size"]);
+    assert_eq!("xxh3", json["parameters"]["hash"]);
+    assert_eq!(1, json["estimate"]);
+    assert_eq!("accurate", json["status"]);
+  }
+
+  #[test]
+  fn cardinality_bounds() {
+    let mut lc = LinearCounter::new(1);
+    for j in 0..31 {
+      lc.offer_hash(j);
+    }
+    let counter = Cardinality::Linear(lc);
+
+    let mut out = vec![];
+    write_cardinality(&mut out, Format::Text, &counter, None, Some(0.95)).unwrap();
+    assert_eq!("111 (52 - 170 at 95% confidence)\n", String::from_utf8(out).unwrap());
+
+    let mut out = vec![];
+    write_cardinality(&mut out, Format::Csv, &counter, None, Some(0.95)).unwrap();
+    let csv = String::from_utf8(out).unwrap();
+    let header = "algorithm,estimate,standard_error,lower_bound,upper_bound\n";
+    assert!(csv.starts_with(&format!("{}linear,111,", header)), "{}", csv);
+    assert!(csv.ends_with(",52,170\n"), "{}", csv);
+  }
+
+  #[test]
+  fn saturated_cardinality_json() {
+    let mut lc = LinearCounter::new(1);
+    for j in 0..32 {
+      lc.offer_hash(j);
+    }
+
+    let mut out = vec![];
+    write_cardinality(&mut out, Format::Json, &Cardinality::Linear(lc), None, Some(0.95)).unwrap();
+    let json: Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!("saturated", json["status"]);
+    assert_eq!(Value::Null, json["standard_error"]);
+    assert_eq!(111, json["lower_bound"]);
+    assert_eq!(Value::Null, json["upper_bound"]);
+  }
+}
@@ -0,0 +1,253 @@
+use md5;
+use std::io::{self, Read, Write, ErrorKind};
+
+/// Magic bytes every sketch file starts with
+const MAGIC: &[u8; 4] = b"ESTS";
+
+/// Version of the file format. Should be incremented on each incompatible change.
+pub const VERSION: u16 = 5;
+
+/// Length of the header fields covered by the checksum: magic, version, ids, size, seed and length
+const HEADER_LENGTH: usize = 32;
+
+/// Type of the sketch stored in a file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SketchKind {
+  LinearCounter,
+  HyperLogLog,
+  StreamSummary,
+
+  /// Linear counter and HyperLogLog fed with the same stream. Payload contains sketch files of both
+  /// estimators one after another.
+  LinearAndHyperLogLog,
+  CountMin
+}
+
+impl SketchKind {
+
+  fn id(self) -> u8 {
+    match self {
+      SketchKind::LinearCounter => 1,
+      SketchKind::HyperLogLog => 2,
+      SketchKind::StreamSummary => 3,
+      SketchKind::LinearAndHyperLogLog => 4,
+      SketchKind::CountMin => 5
+    }
+  }
+
+  fn from_id(id: u8) -> Option<SketchKind> {
+    match id {
+      1 => Some(SketchKind::LinearCounter),
+      2 => Some(SketchKind::HyperLogLog),
+      3 => Some(SketchKind::StreamSummary),
+      4 => Some(SketchKind::LinearAndHyperLogLog),
+      5 => Some(SketchKind::CountMin),
+      _ => None
+    }
+  }
+}
+
+/// Sketch file header
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+  pub kind: SketchKind,
+
+  /// Size of the sketch. Meaning depends on the sketch type: number of buffer words for linear
+  /// counter (alone or combined with HyperLogLog), precision for HyperLogLog, capacity for stream
+  /// summary and width for count-min sketch.
+  pub size: u64,
+
+  /// Hash function used to build the sketch
+  pub hash: u8,
+
+  /// Seed of the hash function
+  pub seed: u64
+}
+
+/// Versioned binary representation of a sketch.
+///
+/// File consists of a header followed by a sketch specific payload. All integers are stored in
+/// little endian byte order:
+///
+/// | Field          | Size |
+/// |----------------|------|
+/// | magic `ESTS`   | 4    |
+/// | version        | 2    |
+/// | sketch type    | 1    |
+/// | hash function  | 1    |
+/// | size           | 8    |
+/// | hash seed      | 8    |
+/// | payload length | 8    |
+/// | MD5 checksum   | 16   |
+/// | payload        | ...  |
+///
+/// Checksum covers all the header fields preceding it and the payload.
+pub struct SketchFile {
+  pub header: Header,
+  pub payload: Vec<u8>
+}
+
+impl SketchFile {
+
+  pub fn new(header: Header, payload: Vec<u8>) -> Self {
+    SketchFile { header, payload }
+  }
+
+  pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    let mut header = Vec::with_capacity(HEADER_LENGTH);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    header.extend_from_slice(&[self.header.kind.id(), self.header.hash]);
+    write_u64(&mut header, self.header.size)?;
+    write_u64(&mut header, self.header.seed)?;
+    write_u64(&mut header, self.payload.len() as u64)?;
+
+    w.write_all(&header)?;
+    w.write_all(&*checksum(&header, &self.payload))?;
+    w.write_all(&self.payload)
+  }
+
+  pub fn read_from<R: Read>(r: &mut R) -> io::Result<SketchFile> {
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+      return Err(invalid_data("Not a sketch file"));
+    }
+
+    let mut version = [0; 2];
+    r.read_exact(&mut version)?;
+    let version = u16::from_le_bytes(version);
+    if version != VERSION {
+      return Err(invalid_data(&format!("Unsupported sketch file version: {}", version)));
+    }
+
+    let mut header = [0; HEADER_LENGTH];
+    header[..4].copy_from_slice(&magic);
+    header[4..6].copy_from_slice(&version.to_le_bytes());
+    r.read_exact(&mut header[6..])?;
+
+    let fields = &mut &header[6..];
+    let mut ids = [0; 2];
+    fields.read_exact(&mut ids)?;
+    let kind = SketchKind::from_id(ids[0])
+      .ok_or_else(|| invalid_data(&format!("Unknown sketch type: {}", ids[0])))?;
+    let hash = ids[1];
+    let size = read_u64(fields)?;
+    let seed = read_u64(fields)?;
+    let length = read_u64(fields)?;
+
+    let mut expected = [0; 16];
+    r.read_exact(&mut expected)?;
+    let mut payload = vec![];
+    r.take(length).read_to_end(&mut payload)?;
+    if payload.len() as u64 != length {
+      return Err(invalid_data("Sketch file is truncated"));
+    }
+    if *checksum(&header, &payload) != expected {
+      return Err(invalid_data("Sketch file checksum mismatch"));
+    }
+
+    Ok(SketchFile { header: Header { kind, size, hash, seed }, payload })
+  }
+
+  /// Checks that the file contains sketch of a given type
+  pub fn expect_kind(&self, kind: SketchKind) -> io::Result<()> {
+    if self.header.kind != kind {
+      return Err(invalid_data(&format!("Expected {:?} sketch, got {:?}", kind, self.header.kind)));
+    }
+    Ok(())
+  }
+}
+
+fn checksum(header: &[u8], payload: &[u8]) -> md5::Digest {
+  let mut context = md5::Context::new();
+  context.consume(header);
+  context.consume(payload);
+  context.compute()
+}
+
+pub fn invalid_data(message: &str) -> io::Error {
+  io::Error::new(ErrorKind::InvalidData, message)
+}
+
+pub fn write_u32<W: Write>(w: &mut W, value: u32) -> io::Result<()> {
+  w.write_all(&value.to_le_bytes())
+}
+
+pub fn write_u64<W: Write>(w: &mut W, value: u64) -> io::Result<()> {
+  w.write_all(&value.to_le_bytes())
+}
+
+pub fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+  let mut bytes = [0; 4];
+  r.read_exact(&mut bytes)?;
+  Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+  let mut bytes = [0; 8];
+  r.read_exact(&mut bytes)?;
+  Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+  use hash;
+
+  fn sample() -> SketchFile {
+    let header = Header { kind: SketchKind::HyperLogLog, size: 12, hash: hash::MD5_ID, seed: 42 };
+    SketchFile::new(header, vec![1, 2, 3])
+  }
+
+  fn write(file: &SketchFile) -> Vec<u8> {
+    let mut bytes = vec![];
+    file.write_to(&mut bytes).unwrap();
+    bytes
+  }
+
+  #[test]
+  fn write_and_read() {
+    let bytes = write(&sample());
+    let file = SketchFile::read_from(&mut &bytes[..]).unwrap();
+
+    assert_eq!(sample().header, file.header);
+    assert_eq!(vec![1, 2, 3], file.payload);
+  }
+
+  #[test]
+  fn wrong_magic() {
+    let mut bytes = write(&sample());
+    bytes[0] = b'X';
+    assert!(SketchFile::read_from(&mut &bytes[..]).is_err());
+  }
+
+  #[test]
+  fn wrong_version() {
+    let mut bytes = write(&sample());
+    bytes[4] = 0xFF;
+    assert!(SketchFile::read_from(&mut &bytes[..]).is_err());
+  }
+
+  #[test]
+  fn corrupted_payload() {
+    let mut bytes = write(&sample());
+    let last = bytes.len() - 1;
+    bytes[last] = 42;
+    assert!(SketchFile::read_from(&mut &bytes[..]).is_err());
+  }
+
+  #[test]
+  fn corrupted_header() {
+    let mut bytes = write(&sample());
+    bytes[10] = 13;
+    assert!(SketchFile::read_from(&mut &bytes[..]).is_err());
+  }
+
+  #[test]
+  fn truncated_payload() {
+    let bytes = write(&sample());
+    assert!(SketchFile::read_from(&mut &bytes[..bytes.len() - 1]).is_err());
+  }
+}
@@ -1,7 +1,27 @@
-use std::collections::{HashMap, BTreeMap};
-use double_linked_list::{DoublyLinkedList, NodeLink};
+use std::borrow::Borrow;
+use std::cmp::Reverse;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, Read, Write};
+use std::str;
+use std::mem::size_of;
+use hashbrown::HashTable;
+use sketch_file::{self, SketchFile, SketchKind, Header};
+use hash;
+use bounds::Bounds;
+use memory::{self, HeapSize};
 
-type BucketMap = BTreeMap<usize, DoublyLinkedList<String>>;
+/// Capacity of the index for a summary of given capacity.
+///
+/// Eviction removes an element from the index and inserts a new one, which leaves a tombstone.
+/// Hash table rehashes in place instead of growing only while it is at most half full, so the
+/// index is kept twice as large as the summary.
+pub fn index_capacity(capacity: usize) -> usize {
+  capacity.saturating_mul(2)
+}
+
+/// Index of an item or a bucket in the arena used as a null link
+const NIL: usize = usize::MAX;
 
 /// Stream Summary structure.
 ///
@@ -10,6 +30,18 @@ type BucketMap = BTreeMap<usize, DoublyLinkedList<String>>;
 /// Efficient Computation of Frequent and Top-k Elements in Data Streams</a>. It allows
 /// estimate top-k elements in a stream storing only constant number of elements in a memory.
 ///
+/// Elements could be of any type implementing `Hash`, `Eq` and `Clone`. For the summary of
+/// `String`s plain `&str` could be given to `offer`.
+///
+/// Monitored items are stored in an arena (`Vec`) and referenced by indices. Items with the same
+/// count form a bucket, which is a doubly linked list threaded through the items themselves. Each
+/// element is stored only once, and an evicted item's slot (and the allocation of its element, if
+/// possible) is reused by the new one, so `offer` doesn't allocate once the summary is full.
+///
+/// As in the paper, buckets form a linked list ordered by count, so both incrementing a count and
+/// finding the minimal bucket for eviction take constant time. Buckets are stored in an arena too,
+/// with the slots of removed buckets kept in a free list.
+///
 /// # Examples
 /// ```
 /// let mut stream  = StreamSummary::new();
@@ -23,44 +55,128 @@ type BucketMap = BTreeMap<usize, DoublyLinkedList<String>>;
 /// assert_eq("hello", top[0].data);
 /// assert_eq(2, top[0].count);
 /// ```
-pub struct StreamSummary {
-  monitored_items: HashMap<String, Item>,
-  buckets: BucketMap,
-  capacity: usize
+pub struct StreamSummary<K = String> {
+  /// Indices of monitored items in the arena, looked up by item data
+  index: HashTable<usize>,
+  hasher: RandomState,
+  items: Vec<Item<K>>,
+
+  /// Buckets of items linked in ascending order of counts
+  buckets: Vec<Bucket>,
+  free_buckets: Vec<usize>,
+  min_bucket: usize,
+  max_bucket: usize,
+  capacity: usize,
+
+  /// Total count of all the elements offered to the summary (length of the stream)
+  total: u64
 }
 
-pub struct Item {
-  pub data: String,
-  bucket_node: NodeLink<String>,
-  pub epsilon: usize,
-  pub count: usize
+pub struct Item<K = String> {
+  pub data: K,
+  pub epsilon: u64,
+  pub count: u64,
+
+  /// Bucket of the item and adjacent items of the same bucket
+  bucket: usize,
+  prev: usize,
+  next: usize
 }
 
-impl Clone for Item {
+/// List of items with the same count in ascending order of errors (`epsilon`), so items with larger
+/// lower bounds come first. Items with the same error are kept in order they've got the count.
+/// Items are evicted from the tail, so the least reliable item of the bucket is evicted first.
+#[derive(Clone, Copy)]
+struct Bucket {
+  count: u64,
+  head: usize,
+  tail: usize,
+
+  /// Adjacent buckets with lower and higher counts
+  prev: usize,
+  next: usize
+}
+
+/// Estimated count of a single element returned by `StreamSummary::count_of`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Frequency {
+
+  /// Element is monitored by the summary, its real count is within the bounds
+  Monitored(Bounds),
+
+  /// Element is not monitored by the summary, it could have occurred at most given number of times
+  Unmonitored(u64)
+}
+
+impl Frequency {
+
+  /// Bounds of the real count. Estimate of an unmonitored element is 0.
+  pub fn bounds(&self) -> Bounds {
+    match *self {
+      Frequency::Monitored(bounds) => bounds,
+      Frequency::Unmonitored(upper) => Bounds { lower: 0, estimate: 0, upper }
+    }
+  }
 
-  fn clone(&self) -> Self {
-    Item {
-      data: self.data.clone(),
-      bucket_node: self.bucket_node.clone(),
-      epsilon: self.epsilon,
-      count: self.count
+  pub fn is_monitored(&self) -> bool {
+    match *self {
+      Frequency::Monitored(_) => true,
+      Frequency::Unmonitored(_) => false
     }
   }
 }
 
-impl Item {
+/// Element returned by `StreamSummary::heavy_hitters`
+pub struct HeavyHitter<'a, K: 'a = String> {
+  pub item: &'a Item<K>,
 
-  fn new(data: &str, node: NodeLink<String>) -> Self {
-    Item {
-      data: data.to_string(),
-      epsilon: 0,
-      bucket_node: node,
-      count: 1
+  /// Element is guaranteed to be a heavy hitter, its lower bound exceeds the threshold
+  pub guaranteed: bool
+}
+
+/// Iterator over monitored items walking the buckets from the maximal one
+struct Descending<'a, K: 'a> {
+  summary: &'a StreamSummary<K>,
+
+  /// Next bucket to walk and next item of the current bucket
+  bucket: usize,
+  item: usize
+}
+
+impl<'a, K> Iterator for Descending<'a, K> {
+
+  type Item = &'a Item<K>;
+
+  fn next(&mut self) -> Option<&'a Item<K>> {
+    while self.item == NIL {
+      if self.bucket == NIL {
+        return None;
+      }
+      self.item = self.summary.buckets[self.bucket].head;
+      self.bucket = self.summary.buckets[self.bucket].prev;
     }
+    let item = &self.summary.items[self.item];
+    self.item = item.next;
+    Some(item)
+  }
+}
+
+impl<K> Item<K> {
+
+  /// Lower bound of the element count. Real count is in range `lower_bound()..=count`.
+  pub fn lower_bound(&self) -> u64 {
+    self.count - self.epsilon
   }
 }
 
-impl StreamSummary {
+impl<K: Hash + Eq + Clone> Default for StreamSummary<K> {
+
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<K: Hash + Eq + Clone> StreamSummary<K> {
 
   pub fn new() -> Self {
     Self::with_capacity(1000)
@@ -68,79 +184,462 @@ impl StreamSummary {
 
   pub fn with_capacity(capacity: usize) -> Self {
     Self {
-      monitored_items: HashMap::with_capacity(capacity),
-      buckets: BTreeMap::new(),
-      capacity: capacity
+      index: HashTable::with_capacity(index_capacity(capacity)),
+      hasher: RandomState::new(),
+      items: Vec::with_capacity(capacity),
+      // Each bucket has at least one item, so there are never more buckets than items
+      buckets: Vec::with_capacity(capacity),
+      free_buckets: Vec::with_capacity(capacity),
+      min_bucket: NIL,
+      max_bucket: NIL,
+      capacity,
+      total: 0
     }
   }
 
-  pub fn estimate_top(&self) -> Vec<&Item> {
-    let mut top : Vec<&Item> = self.monitored_items.values().collect();
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
 
-    top.sort_unstable_by(|a, b| b.count.cmp(&a.count));
-    top
+  /// Total count of all the elements offered to the summary, which is the length of the stream
+  /// for unweighted offers
+  pub fn total(&self) -> u64 {
+    self.total
   }
 
-  fn push_item_to_bucket(buckets: &mut BucketMap, bucket: usize, data: &String) -> NodeLink<String> {
-    buckets.entry(bucket)
-      .or_insert_with(DoublyLinkedList::new)
-      .push_back(data)
+  /// Returns all monitored elements in descending order of counts
+  pub fn estimate_top(&self) -> Vec<&Item<K>> {
+    self.top(self.items.len())
   }
 
-  pub fn offer(&mut self, data: &str) -> usize {
-    if self.monitored_items.contains_key(data) {
-      // Incrementing count on exisiting element
-      let item = self.monitored_items.get_mut(data).unwrap();
-      let count = item.count;
-      let next_count = count + 1;
-      item.count = next_count;
+  /// Returns at most `k` elements with the largest counts in descending order of counts
+  ///
+  /// Buckets are walked from the maximal one, so only the returned elements are visited. Elements
+  /// with the same count are returned in ascending order of errors, so the ones with larger lower
+  /// bounds come first.
+  pub fn top(&self, k: usize) -> Vec<&Item<K>> {
+    self.descending().take(k).collect()
+  }
 
-      // Removing item from current bucket
-      let should_remove_bucket = {
-        let bucket = self.buckets.get_mut(&count).expect("Illegal state");
-        bucket.remove(&item.bucket_node);
-        bucket.empty()
-      };
-      if should_remove_bucket {
-        self.buckets.remove(&count);
+  /// Returns elements which count exceeds `phi` fraction of the total count (eg. all elements
+  /// accounting for more than 1% of the stream for `phi = 0.01`)
+  ///
+  /// All the real heavy hitters are returned, as counts are never underestimated. Element is
+  /// guaranteed to be a heavy hitter if its lower bound exceeds the threshold as well.
+  pub fn heavy_hitters(&self, phi: f64) -> Vec<HeavyHitter<'_, K>> {
+    let threshold = phi * self.total as f64;
+    self.descending()
+      .take_while(|item| item.count as f64 > threshold)
+      .map(|item| HeavyHitter { item, guaranteed: item.lower_bound() as f64 > threshold })
+      .collect()
+  }
+
+  /// Iterates over monitored elements in descending order of counts
+  fn descending(&self) -> Descending<'_, K> {
+    Descending { summary: self, bucket: self.max_bucket, item: NIL }
+  }
+
+  /// Estimated count of given element
+  ///
+  /// For monitored elements count is bounded by `lower_bound()` and `count` of the item. Elements
+  /// which are not monitored could have occurred at most the minimal count of the summary times.
+  pub fn count_of<Q>(&self, data: &Q) -> Frequency where K: Borrow<Q>, Q: Hash + Eq + ?Sized {
+    match self.find(data) {
+      Some(idx) => {
+        let item = &self.items[idx];
+        Frequency::Monitored(Bounds { lower: item.lower_bound(), estimate: item.count, upper: item.count })
+      },
+      None => Frequency::Unmonitored(self.min_count())
+    }
+  }
+
+  /// Returns elements of the estimated top-k which are guaranteed to be in the real top-k of the
+  /// stream
+  ///
+  /// As described in the paper, element is guaranteed to be in the top-k if it's lower bound is
+  /// not less than the count of the (k+1)-th element, which is the upper bound of all the elements
+  /// outside of the top-k.
+  pub fn guaranteed_top(&self, k: usize) -> Vec<&Item<K>> {
+    let threshold = self.guarantee_threshold(k);
+    self.top(k).into_iter()
+      .filter(|item| item.lower_bound() >= threshold)
+      .collect()
+  }
+
+  /// Upper bound of count for all the elements outside of the estimated top-k
+  pub fn guarantee_threshold(&self, k: usize) -> u64 {
+    match self.top(k + 1).get(k) {
+      Some(item) => item.count,
+      None => self.min_count()
+    }
+  }
+
+  /// Merges other summary into this one
+  ///
+  /// Implements parallel Space-Saving merge described in paper: [A parallel space saving
+  /// algorithm for frequent items and the Hurwitz zeta distribution](https://arxiv.org/abs/1401.0702).
+  /// Element not monitored by one of the summaries could have been seen by it at most the minimum
+  /// count of that summary times, so its count and epsilon are increased by that minimum.
+  ///
+  /// Capacity of the resulting summary is the minimum of capacities of both summaries.
+  pub fn merge(&mut self, other: &StreamSummary<K>) {
+    let self_min = self.min_count();
+    let other_min = other.min_count();
+
+    let mut merged: Vec<(K, u64, u64)> = Vec::new();
+    for item in &self.items {
+      merged.push(match other.find(&item.data) {
+        Some(o) => {
+          let o = &other.items[o];
+          (item.data.clone(), item.count + o.count, item.epsilon + o.epsilon)
+        },
+        None => (item.data.clone(), item.count + other_min, item.epsilon + other_min)
+      });
+    }
+    for item in &other.items {
+      if self.find(&item.data).is_none() {
+        merged.push((item.data.clone(), item.count + self_min, item.epsilon + self_min));
       }
+    }
+
+    merged.sort_unstable_by_key(|&(_, count, _)| Reverse(count));
+    self.capacity = self.capacity.min(other.capacity);
+    self.total = self.total.saturating_add(other.total);
+    merged.truncate(self.capacity);
 
-      // Adding item to the next bucket
-      item.bucket_node = Self::push_item_to_bucket(&mut self.buckets, next_count, &item.data);
+    self.index.clear();
+    self.items.clear();
+    self.buckets.clear();
+    self.free_buckets.clear();
+    self.min_bucket = NIL;
+    self.max_bucket = NIL;
+    // Inserting in ascending order, so elements with the same count and error keep their order
+    for (data, count, epsilon) in merged.into_iter().rev() {
+      self.insert_item(data, count, epsilon);
+    }
+  }
 
-      return next_count;
+  /// Approximate number of bytes used by the summary
+  ///
+  /// Includes the hash table index, the arena of items, buckets and heap allocations of elements.
+  pub fn memory_usage(&self) -> usize where K: HeapSize {
+    let elements: usize = self.items.iter().map(|item| item.data.heap_size()).sum();
+    size_of::<Self>()
+      + memory::hash_table_size::<usize>(self.index.capacity())
+      + self.items.capacity() * size_of::<Item<K>>()
+      + self.buckets.capacity() * size_of::<Bucket>()
+      + self.free_buckets.capacity() * size_of::<usize>()
+      + elements
+  }
+
+  /// Calculates the largest capacity of a summary fitting into given number of bytes, when heap
+  /// allocation of each element is `element_size` bytes (eg. average length of a string)
+  pub fn capacity_for_memory(bytes: usize, element_size: usize) -> usize {
+    memory::capacity_for_memory(bytes, |capacity| Self::memory_for_capacity(capacity, element_size))
+  }
 
+  /// Upper bound of memory used by a full summary of given capacity
+  fn memory_for_capacity(capacity: usize, element_size: usize) -> usize {
+    let element = element_size + size_of::<Item<K>>() + size_of::<Bucket>() + size_of::<usize>();
+    size_of::<Self>()
+      + memory::hash_table_size_for_capacity::<usize>(index_capacity(capacity))
+      + capacity * element
+  }
+
+  /// Upper bound of count for elements which are not monitored by the summary
+  fn min_count(&self) -> u64 {
+    if self.items.len() < self.capacity {
+      0
     } else {
-      if self.monitored_items.len() >= self.capacity {
-        // Replacing exisiting element
-        let min_bucket = *self.buckets.keys().min().expect("No element in visited items found");
-
-        let (item, should_remove_bucket) = {
-          let bucket = self.buckets.get_mut(&min_bucket).expect("No bucket found!");
-          let node = bucket.pop_front().expect("No element in a bucket found!");
-          (self.monitored_items.remove(&node).unwrap(), bucket.empty())
-        };
-
-        if should_remove_bucket {
-          self.buckets.remove(&min_bucket);
-        }
+      self.buckets[self.min_bucket].count
+    }
+  }
+
+  /// Returns index of the item monitoring given element
+  fn find<Q>(&self, data: &Q) -> Option<usize> where K: Borrow<Q>, Q: Hash + Eq + ?Sized {
+    let hash = self.hasher.hash_one(data);
+    self.index.find(hash, |&i| self.items[i].data.borrow() == data).cloned()
+  }
+
+  /// Inserts new item with given count
+  ///
+  /// Bucket of the item is searched from the maximal one, so inserting items in ascending order
+  /// of counts (as merge and deserialization do) takes constant time. Items with count not
+  /// greater than the minimal one are inserted in constant time too.
+  fn insert_item(&mut self, data: K, count: u64, epsilon: u64) {
+    let idx = self.items.len();
+    self.items.push(Item { data, epsilon, count, bucket: NIL, prev: NIL, next: NIL });
+
+    let bucket = if self.min_bucket == NIL || count < self.buckets[self.min_bucket].count {
+      self.new_bucket(count, NIL)
+    } else if count == self.buckets[self.min_bucket].count {
+      self.min_bucket
+    } else {
+      let mut prev = self.max_bucket;
+      while self.buckets[prev].count > count {
+        prev = self.buckets[prev].prev;
+      }
+      if self.buckets[prev].count == count { prev } else { self.new_bucket(count, prev) }
+    };
+    self.push_to_bucket(idx, bucket);
+    self.index_item(idx);
+  }
 
-        let new_count = item.count + 1;
-        let new_epsilon = item.epsilon + 1;
-        let new_node = Self::push_item_to_bucket(&mut self.buckets, new_count, &data.to_string());
-        let item = Item {data: data.to_string(), bucket_node: new_node, epsilon: new_epsilon, count: new_count};
-        self.monitored_items.insert(data.to_string(), item);
+  /// Increases count of the item by given weight moving it to the bucket of the new count
+  ///
+  /// Bucket of the new count is searched from the current one, so increments by one take
+  /// constant time.
+  fn increase(&mut self, idx: usize, weight: u64) {
+    let bucket = self.items[idx].bucket;
+    let count = self.buckets[bucket].count.saturating_add(weight);
+    self.items[idx].count = count;
 
-        return 1;
-      } else {
-        // Pushing new element
-        let node = Self::push_item_to_bucket(&mut self.buckets, 1, &data.to_string());
-        let item = Item::new(data, node);
-        self.monitored_items.insert(data.to_string(), item);
+    // Last bucket with count less than the new one
+    let mut prev = bucket;
+    while self.buckets[prev].next != NIL && self.buckets[self.buckets[prev].next].count < count {
+      prev = self.buckets[prev].next;
+    }
+    let next = self.buckets[prev].next;
+    if next != NIL && self.buckets[next].count == count {
+      self.remove_from_bucket(idx);
+      self.push_to_bucket(idx, next);
+    } else if prev == bucket && self.buckets[bucket].head == self.buckets[bucket].tail {
+      // The only item of the bucket, so the bucket itself is moved to the new count
+      self.buckets[bucket].count = count;
+    } else {
+      // Bucket `prev` is not freed here: it's either a different bucket or has other items
+      self.remove_from_bucket(idx);
+      let next = self.new_bucket(count, prev);
+      self.push_to_bucket(idx, next);
+    }
+  }
 
-        return 1;
+  /// Creates an empty bucket with given count and links it after given bucket (or as the minimal
+  /// one, if `prev` is `NIL`)
+  fn new_bucket(&mut self, count: u64, prev: usize) -> usize {
+    let next = if prev == NIL { self.min_bucket } else { self.buckets[prev].next };
+    let bucket = Bucket { count, head: NIL, tail: NIL, prev, next };
+    let idx = match self.free_buckets.pop() {
+      Some(idx) => {
+        self.buckets[idx] = bucket;
+        idx
+      },
+      None => {
+        self.buckets.push(bucket);
+        self.buckets.len() - 1
       }
     };
+    if prev == NIL {
+      self.min_bucket = idx;
+    } else {
+      self.buckets[prev].next = idx;
+    }
+    if next == NIL {
+      self.max_bucket = idx;
+    } else {
+      self.buckets[next].prev = idx;
+    }
+    idx
+  }
+
+  /// Unlinks empty bucket from the list of buckets and puts it to the free list
+  fn free_bucket(&mut self, idx: usize) {
+    let Bucket { prev, next, .. } = self.buckets[idx];
+    if prev == NIL {
+      self.min_bucket = next;
+    } else {
+      self.buckets[prev].next = next;
+    }
+    if next == NIL {
+      self.max_bucket = prev;
+    } else {
+      self.buckets[next].prev = prev;
+    }
+    self.free_buckets.push(idx);
+  }
+
+  /// Adds item to the index of monitored items
+  fn index_item(&mut self, idx: usize) {
+    let StreamSummary { ref mut index, ref hasher, ref items, .. } = *self;
+    let hash = hasher.hash_one(&items[idx].data);
+    index.insert_unique(hash, idx, |&i| hasher.hash_one(&items[i].data));
+  }
+
+  /// Removes item from the index of monitored items
+  fn unindex_item(&mut self, idx: usize) {
+    let hash = self.hasher.hash_one(&self.items[idx].data);
+    if let Ok(entry) = self.index.find_entry(hash, |&i| i == idx) {
+      entry.remove();
+    }
+  }
+
+  /// Inserts item into given bucket after all the items with the same or smaller error
+  ///
+  /// Position is searched from the tail. Elements replacing evicted ones inherit the growing
+  /// minimal count as their error, so the item is usually appended to the tail.
+  fn push_to_bucket(&mut self, idx: usize, bucket: usize) {
+    let epsilon = self.items[idx].epsilon;
+    let mut prev = self.buckets[bucket].tail;
+    while prev != NIL && self.items[prev].epsilon > epsilon {
+      prev = self.items[prev].prev;
+    }
+    let next = if prev == NIL { self.buckets[bucket].head } else { self.items[prev].next };
+
+    if prev == NIL {
+      self.buckets[bucket].head = idx;
+    } else {
+      self.items[prev].next = idx;
+    }
+    if next == NIL {
+      self.buckets[bucket].tail = idx;
+    } else {
+      self.items[next].prev = idx;
+    }
+    let item = &mut self.items[idx];
+    item.bucket = bucket;
+    item.prev = prev;
+    item.next = next;
+  }
+
+  /// Unlinks item from its bucket. Empty bucket is freed.
+  fn remove_from_bucket(&mut self, idx: usize) {
+    let Item { bucket, prev, next, .. } = self.items[idx];
+    if prev == NIL {
+      self.buckets[bucket].head = next;
+    } else {
+      self.items[prev].next = next;
+    }
+    if next == NIL {
+      self.buckets[bucket].tail = prev;
+    } else {
+      self.items[next].prev = prev;
+    }
+    if prev == NIL && next == NIL {
+      self.free_bucket(bucket);
+    }
+  }
+
+  /// Updates the summary with given element. Returns estimated count of the element.
+  pub fn offer<Q>(&mut self, data: &Q) -> u64
+    where K: Borrow<Q>, Q: Hash + Eq + ToOwned<Owned = K> + ?Sized {
+
+    self.offer_weighted(data, 1)
+  }
+
+  /// Updates the summary with given element occurring `weight` times (eg. number of bytes
+  /// transferred). Returns estimated count of the element.
+  ///
+  /// Space-Saving guarantees hold for weighted streams as well: an element replacing the minimal
+  /// one inherits its count as the maximum overestimation. Zero weight doesn't change the summary.
+  pub fn offer_weighted<Q>(&mut self, data: &Q, weight: u64) -> u64
+    where K: Borrow<Q>, Q: Hash + Eq + ToOwned<Owned = K> + ?Sized {
+
+    if weight == 0 {
+      return self.find(data).map_or(0, |idx| self.items[idx].count);
+    }
+
+    self.total = self.total.saturating_add(weight);
+    if let Some(idx) = self.find(data) {
+      // Increasing count of exisiting element and moving it to the bucket of the new count
+      self.increase(idx, weight);
+
+      self.items[idx].count
+
+    } else if self.items.len() >= self.capacity {
+      // Replacing the element of the minimal bucket with the largest error. Slot of the evicted
+      // item is reused.
+      let idx = self.buckets[self.min_bucket].tail;
+      self.unindex_item(idx);
+
+      // New element inherits count of the evicted one, which is the maximum possible
+      // overestimation of its count
+      let item = &mut self.items[idx];
+      data.clone_into(&mut item.data);
+      item.epsilon = item.count;
+
+      self.increase(idx, weight);
+      self.index_item(idx);
+
+      self.items[idx].count
+    } else {
+      // Pushing new element
+      self.insert_item(data.to_owned(), weight, 0);
+
+      weight
+    }
+  }
+}
+
+impl StreamSummary<String> {
+
+  /// Writes the state of the summary in the sketch file format
+  ///
+  /// Payload contains total count and number of monitored elements followed by count, epsilon
+  /// and data of each element in ascending order of counts.
+  pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    let mut payload = vec![];
+    sketch_file::write_u64(&mut payload, self.total)?;
+    sketch_file::write_u64(&mut payload, self.items.len() as u64)?;
+    for item in self.estimate_top().iter().rev() {
+      sketch_file::write_u64(&mut payload, item.count)?;
+      sketch_file::write_u64(&mut payload, item.epsilon)?;
+      sketch_file::write_u32(&mut payload, item.data.len() as u32)?;
+      payload.write_all(item.data.as_bytes())?;
+    }
+    let header = Header {
+      kind: SketchKind::StreamSummary,
+      size: self.capacity as u64,
+      hash: hash::MD5_ID,
+      seed: 0
+    };
+    SketchFile::new(header, payload).write_to(w)
+  }
+
+  /// Reads summary previously written with `write_to`
+  pub fn read_from<R: Read>(r: &mut R) -> io::Result<StreamSummary<String>> {
+    Self::from_sketch(SketchFile::read_from(r)?)
+  }
+
+  pub fn from_sketch(file: SketchFile) -> io::Result<StreamSummary<String>> {
+    file.expect_kind(SketchKind::StreamSummary)?;
+    if file.header.size == 0 {
+      return Err(sketch_file::invalid_data("Invalid stream summary capacity"));
+    }
+
+    let mut payload = &file.payload[..];
+    let total = sketch_file::read_u64(&mut payload)?;
+    let len = sketch_file::read_u64(&mut payload)?;
+    if len > file.header.size {
+      return Err(sketch_file::invalid_data("Stream summary has more elements than its capacity"));
+    }
+    // Each element takes at least 20 bytes (count, epsilon and data length)
+    if len > payload.len() as u64 / 20 {
+      return Err(sketch_file::invalid_data("Stream summary is truncated"));
+    }
+
+    // Capacity may be arbitrarily large in a crafted file, so only the stored elements are preallocated
+    let mut summary = StreamSummary::with_capacity(len as usize);
+    summary.capacity = file.header.size as usize;
+    summary.total = total;
+    for _ in 0..len {
+      let count = sketch_file::read_u64(&mut payload)?;
+      let epsilon = sketch_file::read_u64(&mut payload)?;
+      let data_len = sketch_file::read_u32(&mut payload)? as usize;
+      if payload.len() < data_len {
+        return Err(sketch_file::invalid_data("Stream summary element is truncated"));
+      }
+      let (data, rest) = payload.split_at(data_len);
+      let data = str::from_utf8(data).map_err(|e| sketch_file::invalid_data(&e.to_string()))?;
+      if epsilon > count {
+        return Err(sketch_file::invalid_data("Stream summary element error exceeds its count"));
+      }
+      if summary.find(data).is_some() {
+        return Err(sketch_file::invalid_data(&format!("Duplicate stream summary element: {}", data)));
+      }
+      summary.insert_item(data.to_string(), count, epsilon);
+      payload = rest;
+    }
+    Ok(summary)
   }
 }
 
@@ -148,10 +647,11 @@ impl StreamSummary {
 mod tests {
 
   use super::*;
+  use std::collections::HashMap;
 
   #[test]
   fn shound() {
-    let s = StreamSummary::new();
+    let s: StreamSummary = StreamSummary::new();
     assert_eq!(0, s.estimate_top().len());
   }
 
@@ -195,6 +695,241 @@ mod tests {
     }
   }
 
+  #[test]
+  fn evicted_slots_are_reused() {
+    let mut s = StreamSummary::with_capacity(3);
+    for i in 0..100 {
+      s.offer(&(i % 7).to_string());
+      s.offer("hot");
+    }
+
+    assert_eq!(3, s.items.len());
+    assert_eq!(3, s.items.capacity());
+    assert_eq!(3, s.index.len());
+    assert_eq!("hot", s.estimate_top()[0].data);
+    for (i, item) in s.items.iter().enumerate() {
+      assert_eq!(Some(i), s.find(&item.data));
+    }
+
+    assert_linked(&s);
+  }
+
+  #[test]
+  fn index_is_not_resized_on_eviction() {
+    let mut s = StreamSummary::with_capacity(100);
+    let allocation = s.index.allocation_size();
+    let capacity = s.index.capacity();
+    for i in 0..100_000 {
+      s.offer(&i.to_string());
+      // Tombstones reduce the reported capacity until the table is rehashed in place
+      assert!(s.index.capacity() <= capacity);
+    }
+    assert_eq!(allocation, s.index.allocation_size());
+  }
+
+  #[test]
+  fn buckets_are_ordered_by_count() {
+    let mut s = StreamSummary::with_capacity(50);
+    for i in 0..10_000u64 {
+      // Skewed stream with many distinct counts
+      s.offer(&(i * i % 97 % (i % 13 + 1)));
+      assert_linked(&s);
+    }
+    assert!(s.buckets.len() <= 50);
+    assert_eq!(s.buckets.len() - s.free_buckets.len(), buckets(&s).len());
+  }
+
+  /// Checks that buckets are linked in ascending order of counts and each item is linked to the
+  /// bucket of its count
+  fn assert_linked<K: Hash + Eq + Clone>(s: &StreamSummary<K>) {
+    let mut linked = 0;
+    let mut prev_bucket = NIL;
+    for b in buckets(s) {
+      let bucket = &s.buckets[b];
+      assert_eq!(prev_bucket, bucket.prev);
+      if prev_bucket != NIL {
+        assert!(s.buckets[prev_bucket].count < bucket.count);
+      }
+      let mut i = bucket.head;
+      let mut prev = NIL;
+      while i != NIL {
+        assert_eq!(bucket.count, s.items[i].count);
+        assert_eq!(b, s.items[i].bucket);
+        assert_eq!(prev, s.items[i].prev);
+        if prev != NIL {
+          assert!(s.items[prev].epsilon <= s.items[i].epsilon);
+        }
+        prev = i;
+        i = s.items[i].next;
+        linked += 1;
+      }
+      assert_eq!(prev, bucket.tail);
+      prev_bucket = b;
+    }
+    assert_eq!(prev_bucket, s.max_bucket);
+    assert_eq!(s.items.len(), linked);
+  }
+
+  /// Indices of buckets in ascending order of counts
+  fn buckets<K>(s: &StreamSummary<K>) -> Vec<usize> {
+    let mut buckets = vec![];
+    let mut b = s.min_bucket;
+    while b != NIL {
+      buckets.push(b);
+      b = s.buckets[b].next;
+    }
+    buckets
+  }
+
+  #[test]
+  fn weighted_offers() {
+    let mut s = StreamSummary::with_capacity(10);
+    assert_eq!(10, s.offer_weighted("foo", 10));
+    assert_eq!(3, s.offer_weighted("bar", 3));
+    assert_eq!(15, s.offer_weighted("foo", 5));
+    assert_eq!(4, s.offer("bar"));
+    assert_eq!(0, s.offer_weighted("baz", 0));
+
+    assert_eq!(vec!["foo", "bar"], top_items(&s));
+    assert_eq!(15, s.estimate_top()[0].count);
+    assert_linked(&s);
+  }
+
+  #[test]
+  fn zero_weight_of_monitored_element() {
+    let mut s = StreamSummary::with_capacity(10);
+    s.offer("foo");
+    s.offer("bar");
+    s.offer("baz");
+    assert_eq!(1, s.offer_weighted("foo", 0));
+
+    assert_eq!(1, buckets(&s).len());
+    assert_eq!(3, s.total());
+    assert_linked(&s);
+  }
+
+  #[test]
+  fn weighted_eviction_keeps_bounds() {
+    let mut s = StreamSummary::with_capacity(10);
+    let mut exact = HashMap::new();
+    for (i, item) in stream(3, 1000).iter().enumerate() {
+      let weight = (i * 31 % 17) as u64;
+      s.offer_weighted(item, weight);
+      *exact.entry(item.clone()).or_insert(0) += weight;
+    }
+    assert_linked(&s);
+
+    for item in s.estimate_top() {
+      let real = exact[&item.data];
+      assert!(item.count >= real, "{} overestimated: {} < {}", item.data, item.count, real);
+      assert!(item.lower_bound() <= real, "{} lower bound is too high", item.data);
+    }
+    let min = s.min_count();
+    for (data, count) in exact {
+      if s.find(&data).is_none() {
+        assert!(count <= min, "{} is not monitored: {} > {}", data, count, min);
+      }
+    }
+  }
+
+  #[test]
+  fn count_of_monitored_and_unmonitored() {
+    let mut s = StreamSummary::with_capacity(2);
+    assert_eq!(Frequency::Unmonitored(0), s.count_of("foo"));
+
+    offer(4, &mut s, "foo");
+    offer(2, &mut s, "bar");
+    assert_eq!(Frequency::Unmonitored(2), s.count_of("baz"));
+
+    // Replaces "bar" inheriting its count
+    offer(1, &mut s, "baz");
+    assert_eq!(Frequency::Monitored(Bounds { lower: 4, estimate: 4, upper: 4 }), s.count_of("foo"));
+    assert_eq!(Frequency::Monitored(Bounds { lower: 1, estimate: 3, upper: 3 }), s.count_of("baz"));
+    assert_eq!(Frequency::Unmonitored(3), s.count_of("bar"));
+    assert_eq!(3, s.count_of("bar").bounds().upper);
+    assert!(!s.count_of("bar").is_monitored());
+  }
+
+  #[test]
+  fn top_k() {
+    let mut s = StreamSummary::with_capacity(10);
+    offer(1, &mut s, "a");
+    offer(5, &mut s, "b");
+    offer(3, &mut s, "c");
+    offer(3, &mut s, "d");
+    offer(4, &mut s, "e");
+
+    let top: Vec<&str> = s.top(3).iter().map(|item| item.data.as_str()).collect();
+    assert_eq!(vec!["b", "e", "c"], top);
+    assert_eq!(5, s.top(10).len());
+    assert!(s.top(0).is_empty());
+    assert_eq!(vec!["b", "e", "c", "d", "a"], top_items(&s));
+  }
+
+  #[test]
+  fn heavy_hitters() {
+    let mut s = StreamSummary::with_capacity(3);
+    offer(50, &mut s, "foo");
+    offer(30, &mut s, "bar");
+    offer(10, &mut s, "baz");
+    // Evicts "baz", so "qux" has count 20 with epsilon 10
+    offer(10, &mut s, "qux");
+    s.offer_weighted("bar", 0);
+    assert_eq!(100, s.total());
+
+    let hitters: Vec<(&str, bool)> = s.heavy_hitters(0.15).iter()
+      .map(|h| (h.item.data.as_str(), h.guaranteed))
+      .collect();
+    assert_eq!(vec![("foo", true), ("bar", true), ("qux", false)], hitters);
+    assert_eq!(1, s.heavy_hitters(0.4).len());
+    assert!(s.heavy_hitters(0.5).is_empty());
+  }
+
+  #[test]
+  fn memory() {
+    let budget = 1 << 20;
+    let capacity = StreamSummary::<String>::capacity_for_memory(budget, 10);
+    let mut summary = StreamSummary::with_capacity(capacity);
+    for i in 0..capacity {
+      summary.offer(&format!("{:010}", i));
+    }
+    assert!(summary.memory_usage() <= budget, "Too much memory used: {}", summary.memory_usage());
+    assert!(summary.memory_usage() > budget / 2, "Too little memory used: {}", summary.memory_usage());
+  }
+
+  #[test]
+  fn integer_keys() {
+    let mut s = StreamSummary::with_capacity(2);
+    s.offer(&42u64);
+    s.offer(&42u64);
+    s.offer(&7u64);
+
+    let top = s.estimate_top();
+    assert_eq!(42, top[0].data);
+    assert_eq!(2, top[0].count);
+  }
+
+  #[test]
+  fn tuple_keys() {
+    let mut s = StreamSummary::new();
+    s.offer(&(1, "foo"));
+    s.offer(&(1, "foo"));
+    s.offer(&(2, "foo"));
+
+    assert_eq!((1, "foo"), s.estimate_top()[0].data);
+  }
+
+  #[test]
+  fn merge_integer_keys() {
+    let mut a = StreamSummary::new();
+    let mut b = StreamSummary::new();
+    a.offer(&1u32);
+    b.offer(&1u32);
+
+    a.merge(&b);
+    assert_eq!(2, a.estimate_top()[0].count);
+  }
+
   fn offer(n: usize, s: &mut StreamSummary, data: &str) {
     for _ in 0..n {
       s.offer(data);
@@ -207,6 +942,205 @@ mod tests {
       .collect()
   }
 
+  #[test]
+  fn epsilon_of_replaced_element() {
+    let mut s = StreamSummary::with_capacity(2);
+
+    offer(3, &mut s, "foo");
+    offer(2, &mut s, "bar");
+    assert_eq!(3, s.offer("baz"));
+
+    let top = s.estimate_top();
+    let baz = top.iter().find(|i| i.data == "baz").unwrap();
+    assert_eq!(2, baz.epsilon);
+  }
+
+  #[test]
+  fn guaranteed_top_without_replacements() {
+    let mut s = StreamSummary::new();
+    offer(3, &mut s, "foo");
+    offer(2, &mut s, "bar");
+    offer(1, &mut s, "baz");
+
+    let top: Vec<&str> = s.guaranteed_top(2).iter().map(|i| i.data.as_str()).collect();
+    assert_eq!(vec!["foo", "bar"], top);
+  }
+
+  #[test]
+  fn guaranteed_top_with_replacements() {
+    let mut s = StreamSummary::with_capacity(3);
+    offer(10, &mut s, "foo");
+    offer(4, &mut s, "bar");
+    offer(3, &mut s, "baz");
+    offer(2, &mut s, "qux");
+
+    // qux replaces baz and gets count 5 with epsilon 3, so it could not be guaranteed
+    // to be in top-2, because its lower bound is less than the count of bar
+    let top: Vec<&str> = s.guaranteed_top(2).iter().map(|i| i.data.as_str()).collect();
+    assert_eq!(vec!["foo"], top);
+    assert_eq!(4, s.guarantee_threshold(2));
+  }
+
+  #[test]
+  fn guaranteed_top_with_ties() {
+    let mut s = StreamSummary::with_capacity(3);
+    offer(10, &mut s, "foo");
+    offer(3, &mut s, "bar");
+    offer(2, &mut s, "baz");
+    offer(1, &mut s, "qux");
+
+    // qux replaces baz and gets count 3 with epsilon 2, so it ties with bar. bar has the larger
+    // lower bound, so it's ranked higher and is guaranteed to be in top-2.
+    let top: Vec<&str> = s.estimate_top().iter().map(|i| i.data.as_str()).collect();
+    assert_eq!(vec!["foo", "bar", "qux"], top);
+    let top: Vec<&str> = s.guaranteed_top(2).iter().map(|i| i.data.as_str()).collect();
+    assert_eq!(vec!["foo", "bar"], top);
+    assert_eq!(3, s.guarantee_threshold(2));
+  }
+
+  #[test]
+  fn ties_are_ordered_by_error() {
+    let mut s = StreamSummary::with_capacity(2);
+    s.offer("foo");
+    s.offer("bar");
+    // baz replaces bar and gets count 2 with epsilon 1, then foo gets the same count exactly
+    s.offer("baz");
+    s.offer("foo");
+
+    assert_eq!(vec!["foo", "baz"], top_items(&s));
+    assert_eq!("foo", s.top(1)[0].data);
+    assert_linked(&s);
+  }
+
+  #[test]
+  fn merge_disjoint() {
+    let mut a = StreamSummary::new();
+    let mut b = StreamSummary::new();
+    offer(3, &mut a, "foo");
+    offer(2, &mut b, "bar");
+    offer(4, &mut b, "baz");
+
+    a.merge(&b);
+    assert_eq!(vec!["baz", "foo", "bar"], top_items(&a));
+    assert!(a.estimate_top().iter().all(|i| i.epsilon == 0));
+  }
+
+  #[test]
+  fn merge_sums_counts() {
+    let mut a = StreamSummary::new();
+    let mut b = StreamSummary::new();
+    offer(3, &mut a, "foo");
+    offer(2, &mut b, "foo");
+    a.merge(&b);
+
+    let top = a.estimate_top();
+    assert_eq!(1, top.len());
+    assert_eq!(5, top[0].count);
+    assert_eq!(5, a.total());
+  }
+
+  #[test]
+  fn merge_keeps_epsilon_bounds() {
+    let streams = [stream(0, 500), stream(7, 500)];
+    let mut exact = HashMap::new();
+    let mut summaries = vec![];
+    for stream in streams.iter() {
+      let mut s = StreamSummary::with_capacity(10);
+      for item in stream {
+        s.offer(item);
+        *exact.entry(item.clone()).or_insert(0) += 1;
+      }
+      summaries.push(s);
+    }
+
+    let mut merged = summaries.remove(0);
+    merged.merge(&summaries[0]);
+
+    let top = merged.estimate_top();
+    assert_eq!(10, top.len());
+    for item in top {
+      let real = exact[&item.data];
+      assert!(item.count >= real, "{} overestimated: {} < {}", item.data, item.count, real);
+      assert!(item.count - item.epsilon <= real, "{} lower bound is too high", item.data);
+    }
+  }
+
+  #[test]
+  fn merge_capacity_is_minimal() {
+    let mut a = StreamSummary::with_capacity(10);
+    let mut b = StreamSummary::with_capacity(2);
+    offer(1, &mut a, "foo");
+    offer(3, &mut b, "bar");
+    offer(2, &mut b, "baz");
+    offer(1, &mut b, "qux");
+
+    a.merge(&b);
+    assert_eq!(2, a.estimate_top().len());
+  }
+
+  #[test]
+  fn write_and_read() {
+    let mut s = StreamSummary::with_capacity(2);
+    offer(4, &mut s, "foo");
+    offer(2, &mut s, "bar");
+    offer(1, &mut s, "baz");
+
+    let mut bytes = vec![];
+    s.write_to(&mut bytes).unwrap();
+    let restored = StreamSummary::read_from(&mut &bytes[..]).unwrap();
+
+    assert_eq!(2, restored.capacity);
+    assert_eq!(7, restored.total());
+    let top = restored.estimate_top();
+    assert_eq!(vec!["foo", "baz"], top_items(&restored));
+    assert_eq!((4, 0), (top[0].count, top[0].epsilon));
+    assert_eq!((3, 2), (top[1].count, top[1].epsilon));
+  }
+
+  #[test]
+  fn corrupted_capacity() {
+    let mut s = StreamSummary::with_capacity(2);
+    offer(4, &mut s, "foo");
+    let mut bytes = vec![];
+    s.write_to(&mut bytes).unwrap();
+
+    // Capacity is stored in the header at offset 8
+    bytes[15] = 0x7F;
+    assert!(StreamSummary::read_from(&mut &bytes[..]).is_err());
+  }
+
+  /// Sketch file of a summary with given `(count, epsilon, data)` elements
+  fn sketch(items: &[(u64, u64, &str)]) -> SketchFile {
+    let mut payload = vec![];
+    sketch_file::write_u64(&mut payload, items.iter().map(|item| item.0).sum()).unwrap();
+    sketch_file::write_u64(&mut payload, items.len() as u64).unwrap();
+    for &(count, epsilon, data) in items {
+      sketch_file::write_u64(&mut payload, count).unwrap();
+      sketch_file::write_u64(&mut payload, epsilon).unwrap();
+      sketch_file::write_u32(&mut payload, data.len() as u32).unwrap();
+      payload.extend_from_slice(data.as_bytes());
+    }
+    let header = Header { kind: SketchKind::StreamSummary, size: 3, hash: hash::MD5_ID, seed: 0 };
+    SketchFile::new(header, payload)
+  }
+
+  #[test]
+  fn read_invalid_elements() {
+    assert!(StreamSummary::from_sketch(sketch(&[(1, 0, "foo"), (2, 1, "bar")])).is_ok());
+    assert!(StreamSummary::from_sketch(sketch(&[(1, 0, "foo"), (2, 3, "bar")])).is_err());
+    assert!(StreamSummary::from_sketch(sketch(&[(1, 0, "foo"), (2, 0, "foo")])).is_err());
+  }
+
+  /// Skewed stream of numbers where number `i` is repeated `(i + shift) % 20` times
+  fn stream(shift: usize, size: usize) -> Vec<String> {
+    (0..size)
+      .flat_map(|i| {
+        let n = (i + shift) % 20;
+        (0..n).map(move |_| (i % 50).to_string())
+      })
+      .collect()
+  }
+
   #[test]
   fn should_count_occurrences_correctly() {
     let mut s = StreamSummary::new();
@@ -0,0 +1,394 @@
+use std::cmp::Reverse;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::mem::size_of;
+use hashbrown::HashTable;
+use count_min_sketch::CountMinSketch;
+use stream_summary::{self, StreamSummary};
+use hash::HashFunction;
+use memory::{self, HeapSize};
+
+/// Names of algorithms accepted by `est top --algorithm`
+pub const ALGORITHMS: &[&str] = &["space-saving", "cms-heap"];
+
+/// Structure estimating the most frequent elements of a stream of strings
+///
+/// Implemented by `StreamSummary` (Space-Saving) and by `CountMinHeap`, so the algorithms could be
+/// used interchangeably.
+pub trait TopK {
+
+  /// Name of the algorithm reported in the output
+  fn algorithm(&self) -> &'static str;
+
+  /// Parameters of the structure reported in the output
+  fn parameters(&self) -> Vec<(&'static str, u64)>;
+
+  /// Updates the structure with given element occurring `weight` times. Returns estimated count
+  /// of the element.
+  fn offer_weighted(&mut self, data: &str, weight: u64) -> u64;
+
+  /// Updates the structure with given element. Returns estimated count of the element.
+  fn offer(&mut self, data: &str) -> u64 {
+    self.offer_weighted(data, 1)
+  }
+
+  /// Returns at most `k` elements with the largest counts in descending order of counts
+  fn top(&self, k: usize) -> Vec<Ranked<'_>>;
+
+  /// Total count of all the elements offered to the structure
+  fn total(&self) -> u64;
+
+  /// Approximate number of bytes used by the structure
+  fn memory_usage(&self) -> usize;
+}
+
+/// Element of the estimated top
+pub struct Ranked<'a> {
+  pub data: &'a str,
+  pub count: u64,
+
+  /// Lower bound of the element count. Real count is in range `lower_bound..=count`.
+  pub lower_bound: u64,
+
+  /// Element is guaranteed to be in the real top-k
+  pub guaranteed: bool
+}
+
+impl TopK for StreamSummary<String> {
+
+  fn algorithm(&self) -> &'static str {
+    "space-saving"
+  }
+
+  fn parameters(&self) -> Vec<(&'static str, u64)> {
+    vec![("capacity", self.capacity() as u64)]
+  }
+
+  fn offer_weighted(&mut self, data: &str, weight: u64) -> u64 {
+    StreamSummary::offer_weighted(self, data, weight)
+  }
+
+  fn top(&self, k: usize) -> Vec<Ranked<'_>> {
+    let top = StreamSummary::top(self, k);
+    let threshold = self.guarantee_threshold(top.len());
+    top.into_iter()
+      .map(|item| Ranked {
+        data: &item.data,
+        count: item.count,
+        lower_bound: item.lower_bound(),
+        guaranteed: item.lower_bound() >= threshold
+      })
+      .collect()
+  }
+
+  fn total(&self) -> u64 {
+    StreamSummary::total(self)
+  }
+
+  fn memory_usage(&self) -> usize {
+    StreamSummary::memory_usage(self)
+  }
+}
+
+/// Top-k elements tracked by a count-min sketch and a min-heap
+///
+/// Each element is counted by the sketch, and the heap keeps `capacity` elements with the largest
+/// estimated counts. New element replaces the minimal one in the heap only if its estimate is
+/// larger, so unlike Space-Saving rare elements never push the frequent ones out and the error of
+/// counts doesn't depend on the order of the stream. Counts are bounded by the sketch error, which
+/// is a fraction of the total count.
+///
+/// Elements are stored in slots which are referenced by the heap and by the index, so each element
+/// is stored only once and slots are reused when the heap is full.
+pub struct CountMinHeap {
+  sketch: CountMinSketch,
+
+  /// Slots of elements in the heap, looked up by element data
+  index: HashTable<usize>,
+  hasher: RandomState,
+  slots: Vec<Slot>,
+
+  /// Binary min-heap of slots by count
+  heap: Vec<usize>,
+  capacity: usize
+}
+
+struct Slot {
+  data: String,
+
+  /// Estimated count of the element when it was offered last time
+  count: u64,
+
+  /// Position of the slot in the heap
+  position: usize
+}
+
+impl CountMinHeap {
+
+  /// Creates a structure keeping `capacity` elements with counts estimated by a count-min sketch of
+  /// given dimensions
+  pub fn new(capacity: usize, width: usize, depth: usize, hasher: HashFunction) -> CountMinHeap {
+    CountMinHeap {
+      sketch: CountMinSketch::with_hasher(width, depth, hasher),
+      index: HashTable::with_capacity(stream_summary::index_capacity(capacity)),
+      hasher: RandomState::new(),
+      slots: Vec::with_capacity(capacity),
+      heap: Vec::with_capacity(capacity),
+      capacity
+    }
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  /// Calculates the largest capacity of a heap (not including the sketch) fitting into given number
+  /// of bytes, when heap allocation of each element is `element_size` bytes
+  pub fn capacity_for_memory(bytes: usize, element_size: usize) -> usize {
+    memory::capacity_for_memory(bytes, |capacity| Self::memory_for_capacity(capacity, element_size))
+  }
+
+  /// Upper bound of memory used by a full heap of given capacity, not including the sketch
+  pub fn memory_for_capacity(capacity: usize, element_size: usize) -> usize {
+    let element = element_size + size_of::<Slot>() + size_of::<usize>();
+    size_of::<Self>() - size_of::<CountMinSketch>()
+      + memory::hash_table_size_for_capacity::<usize>(stream_summary::index_capacity(capacity))
+      + capacity * element
+  }
+
+  pub fn sketch(&self) -> &CountMinSketch {
+    &self.sketch
+  }
+
+  /// Returns slot of given element if it's in the heap
+  fn find(&self, data: &str) -> Option<usize> {
+    let hash = self.hasher.hash_one(data);
+    self.index.find(hash, |&slot| self.slots[slot].data == data).cloned()
+  }
+
+  /// Adds slot to the index of elements
+  fn index_slot(&mut self, slot: usize) {
+    let CountMinHeap { ref mut index, ref hasher, ref slots, .. } = *self;
+    let hash = hasher.hash_one(slots[slot].data.as_str());
+    index.insert_unique(hash, slot, |&s| hasher.hash_one(slots[s].data.as_str()));
+  }
+
+  /// Removes slot from the index of elements
+  fn unindex_slot(&mut self, slot: usize) {
+    let hash = self.hasher.hash_one(self.slots[slot].data.as_str());
+    if let Ok(entry) = self.index.find_entry(hash, |&s| s == slot) {
+      entry.remove();
+    }
+  }
+
+  /// Moves the slot at given position of the heap down, while it's larger than its children
+  fn sift_down(&mut self, mut position: usize) {
+    loop {
+      let mut smallest = position;
+      for child in &[2 * position + 1, 2 * position + 2] {
+        if *child < self.heap.len() && self.count_at(*child) < self.count_at(smallest) {
+          smallest = *child;
+        }
+      }
+      if smallest == position {
+        break;
+      }
+      self.swap(position, smallest);
+      position = smallest;
+    }
+  }
+
+  /// Moves the slot at given position of the heap up, while it's smaller than its parent
+  fn sift_up(&mut self, mut position: usize) {
+    while position > 0 {
+      let parent = (position - 1) / 2;
+      if self.count_at(parent) <= self.count_at(position) {
+        break;
+      }
+      self.swap(position, parent);
+      position = parent;
+    }
+  }
+
+  fn count_at(&self, position: usize) -> u64 {
+    self.slots[self.heap[position]].count
+  }
+
+  fn swap(&mut self, a: usize, b: usize) {
+    self.heap.swap(a, b);
+    self.slots[self.heap[a]].position = a;
+    self.slots[self.heap[b]].position = b;
+  }
+}
+
+impl TopK for CountMinHeap {
+
+  fn algorithm(&self) -> &'static str {
+    "cms-heap"
+  }
+
+  fn parameters(&self) -> Vec<(&'static str, u64)> {
+    vec![
+      ("capacity", self.capacity as u64),
+      ("width", self.sketch.width() as u64),
+      ("depth", self.sketch.depth() as u64)
+    ]
+  }
+
+  fn offer_weighted(&mut self, data: &str, weight: u64) -> u64 {
+    let count = self.sketch.offer_weighted_bytes(data.as_bytes(), weight);
+    if weight == 0 {
+      return count;
+    }
+
+    if let Some(slot) = self.find(data) {
+      // Count could only grow, so the element is moved down the heap
+      self.slots[slot].count = count;
+      let position = self.slots[slot].position;
+      self.sift_down(position);
+    } else if self.heap.len() < self.capacity {
+      let slot = self.slots.len();
+      self.slots.push(Slot { data: data.to_string(), count, position: slot });
+      self.heap.push(slot);
+      self.index_slot(slot);
+      self.sift_up(slot);
+    } else if self.capacity > 0 && count > self.count_at(0) {
+      // Replacing the minimal element. Its slot (and the allocation of its data) is reused.
+      let slot = self.heap[0];
+      self.unindex_slot(slot);
+      data.clone_into(&mut self.slots[slot].data);
+      self.slots[slot].count = count;
+      self.index_slot(slot);
+      self.sift_down(0);
+    }
+    count
+  }
+
+  /// Counts are estimated by the sketch at the time of the call, as they could have grown since
+  /// the elements were offered. Elements are never guaranteed to be in the real top-k.
+  fn top(&self, k: usize) -> Vec<Ranked<'_>> {
+    let mut top: Vec<Ranked> = self.slots.iter()
+      .map(|slot| {
+        let bounds = self.sketch.estimate_bytes_with_bounds(slot.data.as_bytes());
+        Ranked { data: &slot.data, count: bounds.estimate, lower_bound: bounds.lower, guaranteed: false }
+      })
+      .collect();
+    top.sort_unstable_by_key(|item| Reverse(item.count));
+    top.truncate(k);
+    top
+  }
+
+  fn total(&self) -> u64 {
+    self.sketch.total()
+  }
+
+  fn memory_usage(&self) -> usize {
+    let elements: usize = self.slots.iter().map(|slot| slot.data.heap_size()).sum();
+    size_of::<Self>() - size_of::<CountMinSketch>()
+      + self.sketch.memory_usage()
+      + memory::hash_table_size::<usize>(self.index.capacity())
+      + self.slots.capacity() * size_of::<Slot>()
+      + self.heap.capacity() * size_of::<usize>()
+      + elements
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn offer_all(top: &mut dyn TopK, stream: &[(&str, u64)]) {
+    for &(data, weight) in stream {
+      top.offer_weighted(data, weight);
+    }
+  }
+
+  fn top_items(top: &dyn TopK, k: usize) -> Vec<(&str, u64)> {
+    top.top(k).iter().map(|r| (r.data, r.count)).collect()
+  }
+
+  #[test]
+  fn algorithms_agree_on_exact_counts() {
+    let stream = [("foo", 5), ("bar", 2), ("baz", 1), ("foo", 1), ("bar", 2)];
+    let mut summary = StreamSummary::with_capacity(10);
+    let mut heap = CountMinHeap::new(10, 1000, 4, HashFunction::default());
+    offer_all(&mut summary, &stream);
+    offer_all(&mut heap, &stream);
+
+    let expected = vec![("foo", 6), ("bar", 4)];
+    assert_eq!(expected, top_items(&summary, 2));
+    assert_eq!(expected, top_items(&heap, 2));
+    assert_eq!(11, summary.total());
+    assert_eq!(11, heap.total());
+  }
+
+  #[test]
+  fn rare_elements_do_not_replace_frequent_ones() {
+    let mut heap = CountMinHeap::new(2, 1000, 4, HashFunction::default());
+    heap.offer_weighted("foo", 10);
+    heap.offer_weighted("bar", 5);
+    for i in 0..100 {
+      heap.offer(&i.to_string());
+    }
+
+    assert_eq!(vec![("foo", 10), ("bar", 5)], top_items(&heap, 10));
+    assert_eq!(2, heap.slots.len());
+  }
+
+  #[test]
+  fn frequent_element_replaces_minimal_one() {
+    let mut heap = CountMinHeap::new(2, 1000, 4, HashFunction::default());
+    heap.offer_weighted("foo", 10);
+    heap.offer_weighted("bar", 1);
+    heap.offer_weighted("baz", 3);
+
+    assert_eq!(vec![("foo", 10), ("baz", 3)], top_items(&heap, 10));
+    assert_eq!(None, heap.find("bar"));
+    assert!(!heap.top(1)[0].guaranteed);
+  }
+
+  #[test]
+  fn heap_is_ordered() {
+    let mut heap = CountMinHeap::new(20, 100, 3, HashFunction::default());
+    for i in 0..5000u64 {
+      heap.offer(&(i * i % 101).to_string());
+      for position in 1..heap.heap.len() {
+        assert!(heap.count_at((position - 1) / 2) <= heap.count_at(position));
+      }
+      for (position, slot) in heap.heap.iter().enumerate() {
+        assert_eq!(position, heap.slots[*slot].position);
+      }
+    }
+  }
+
+  #[test]
+  fn index_is_not_resized_on_replacement() {
+    let mut heap = CountMinHeap::new(100, 1000, 4, HashFunction::default());
+    let allocation = heap.index.allocation_size();
+    for i in 0..100_000u64 {
+      // Each element is heavier than all the previous ones, so it replaces the minimal one
+      heap.offer_weighted(&i.to_string(), i + 1);
+    }
+    assert_eq!(allocation, heap.index.allocation_size());
+  }
+
+  #[test]
+  fn memory() {
+    let heap = CountMinHeap::new(100, 1000, 4, HashFunction::default());
+    assert!(heap.memory_usage() > heap.sketch().memory_usage());
+  }
+
+  #[test]
+  fn capacity_for_memory() {
+    let budget = 1 << 20;
+    let capacity = CountMinHeap::capacity_for_memory(budget, 10);
+    let mut heap = CountMinHeap::new(capacity, 1, 1, HashFunction::default());
+    for i in 0..capacity {
+      heap.offer(&format!("{:010}", i));
+    }
+    let usage = heap.memory_usage() - heap.sketch().memory_usage();
+    assert!(usage <= budget, "Too much memory used: {}", usage);
+    assert!(usage > budget / 2, "Too little memory used: {}", usage);
+  }
+}
@@ -0,0 +1,102 @@
+/// Parses a number with an optional decimal suffix (`K`, `M`, `G` or `T`), eg. `50M` or `1.5K`
+pub fn parse_count(value: &str) -> Result<u64, String> {
+  let error = || format!("Invalid number: '{}'", value);
+  let value = value.trim();
+  let (number, multiplier) = match value.chars().last().map(|c| c.to_ascii_uppercase()) {
+    Some('K') => (&value[..value.len() - 1], 1e3),
+    Some('M') => (&value[..value.len() - 1], 1e6),
+    Some('G') => (&value[..value.len() - 1], 1e9),
+    Some('T') => (&value[..value.len() - 1], 1e12),
+    _ => (value, 1.0)
+  };
+  if let Ok(number) = number.parse::<u64>() {
+    return number.checked_mul(multiplier as u64).ok_or_else(error);
+  }
+  match number.parse::<f64>() {
+    Ok(number) if number >= 0.0 && (number * multiplier) < u64::MAX as f64 =>
+      Ok((number * multiplier).round() as u64),
+    _ => Err(error())
+  }
+}
+
+/// Parses a ratio given as a fraction (`0.01`) or in percents (`1%`). Ratio should be between
+/// 0 and 1 exclusive.
+pub fn parse_ratio(value: &str) -> Result<f64, String> {
+  let value = value.trim();
+  let ratio = match value.strip_suffix('%') {
+    Some(percents) => percents.trim().parse::<f64>().map(|p| p / 100.0),
+    None => value.parse::<f64>()
+  };
+  match ratio {
+    Ok(ratio) if ratio > 0.0 && ratio < 1.0 => Ok(ratio),
+    Ok(_) => Err(format!("Ratio should be between 0 and 1: '{}'", value)),
+    Err(_) => Err(format!("Invalid ratio: '{}'", value))
+  }
+}
+
+/// Parses a size in bytes with an optional suffix, eg. `64MiB` or `1G`. Binary (`KiB`, `MiB`,
+/// `GiB`, `TiB`) and decimal (`KB`, `MB`, `GB`, `TB`) suffixes are supported. Single letter
+/// suffixes (`K`, `M`, `G`, `T`) are binary.
+pub fn parse_bytes(value: &str) -> Result<u64, String> {
+  let value = value.trim();
+  let split = value.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(value.len());
+  let (number, suffix) = value.split_at(split);
+  let multiplier: u64 = match suffix.to_ascii_uppercase().as_str() {
+    "" | "B" => 1,
+    "K" | "KIB" => 1 << 10,
+    "M" | "MIB" => 1 << 20,
+    "G" | "GIB" => 1 << 30,
+    "T" | "TIB" => 1 << 40,
+    "KB" => 1_000,
+    "MB" => 1_000_000,
+    "GB" => 1_000_000_000,
+    "TB" => 1_000_000_000_000,
+    _ => return Err(format!("Invalid size: '{}'", value))
+  };
+  match number.trim().parse::<f64>() {
+    Ok(number) if number >= 0.0 && number * (multiplier as f64) < u64::MAX as f64 =>
+      Ok((number * multiplier as f64) as u64),
+    _ => Err(format!("Invalid size: '{}'", value))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn counts() {
+    assert_eq!(Ok(42), parse_count("42"));
+    assert_eq!(Ok(50_000_000), parse_count("50M"));
+    assert_eq!(Ok(1500), parse_count("1.5k"));
+    assert_eq!(Ok(2_000_000_000_000), parse_count("2T"));
+    assert!(parse_count("").is_err());
+    assert!(parse_count("M").is_err());
+    assert!(parse_count("-1").is_err());
+    assert!(parse_count("10X").is_err());
+    assert!(parse_count("20000000000T").is_err());
+  }
+
+  #[test]
+  fn bytes() {
+    assert_eq!(Ok(100), parse_bytes("100"));
+    assert_eq!(Ok(64 << 20), parse_bytes("64MiB"));
+    assert_eq!(Ok(64 << 20), parse_bytes("64m"));
+    assert_eq!(Ok(1536), parse_bytes("1.5 KiB"));
+    assert_eq!(Ok(2_000_000_000), parse_bytes("2GB"));
+    assert!(parse_bytes("MiB").is_err());
+    assert!(parse_bytes("10XB").is_err());
+    assert!(parse_bytes("-1K").is_err());
+  }
+
+  #[test]
+  fn ratios() {
+    assert_eq!(Ok(0.01), parse_ratio("1%"));
+    assert_eq!(Ok(0.005), parse_ratio("0.005"));
+    assert_eq!(Ok(0.025), parse_ratio("2.5 %"));
+    assert!(parse_ratio("0").is_err());
+    assert!(parse_ratio("100%").is_err());
+    assert!(parse_ratio("abc%").is_err());
+  }
+}